@@ -0,0 +1,790 @@
+use super::{store::BlockStore, Block};
+use crate::block_stm::svm_memory::SVMMemory;
+use crate::executor::{
+    engine::{Busy, ExecutionEngine},
+    execute_tx,
+    types::{Receipt, TxBody, TxResult, TxStatus},
+    Executed,
+};
+use crate::svm::svm::SVM;
+use dashmap::DashMap;
+use futures::future::join_all;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+use tokio::sync::{mpsc, oneshot, Mutex, OwnedMutexGuard, OwnedSemaphorePermit};
+use tokio::time::{timeout_at, Instant};
+
+/// Times a block is stored again after a failed write before the builder
+/// gives up and stops.
+const STORE_RETRIES: u32 = 3;
+
+/// Runs one transaction of a block, `execute_tx` on the node's SVM unless
+/// the producer is started with another.
+pub type Execute = Arc<dyn Fn(&TxBody) -> Executed + Send + Sync>;
+
+/// When the builder seals a block.
+#[derive(Clone, Debug)]
+pub struct BlockConfig {
+    /// most transactions in one block
+    pub max_txs: usize,
+    /// how long a block stays open after its first transaction arrives
+    pub interval: Duration,
+}
+
+impl Default for BlockConfig {
+    fn default() -> Self {
+        Self {
+            max_txs: 1_000,
+            interval: Duration::from_millis(100),
+        }
+    }
+}
+
+/// How the transactions of one submitted batch are executed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchMode {
+    /// each transaction on its own, as if submitted separately
+    #[default]
+    Independent,
+    /// one after another, in the given order, all in the same block
+    Block,
+}
+
+impl BatchMode {
+    /// Most transactions of a batch sent to the same block; a bigger
+    /// independent batch is spread over several.
+    fn part_size(self, txs: usize, max_txs: usize) -> usize {
+        match self {
+            BatchMode::Independent => max_txs.max(1),
+            BatchMode::Block => txs.max(1),
+        }
+    }
+
+    /// Splits `txs` into groups that run one transaction after another;
+    /// the groups run in parallel.
+    fn units(self, txs: Vec<TxBody>) -> Vec<Vec<TxBody>> {
+        match self {
+            BatchMode::Independent => txs.into_iter().map(|tx| vec![tx]).collect(),
+            BatchMode::Block => vec![txs],
+        }
+    }
+}
+
+struct Submission {
+    txs: Vec<TxBody>,
+    mode: BatchMode,
+    /// one result per transaction, in submission order
+    done: oneshot::Sender<Vec<TxResult>>,
+    /// room in the engine's queue, given back once the block is sealed
+    admitted: OwnedSemaphorePermit,
+}
+
+/// Entry point of submitted transactions. They are collected into blocks,
+/// executed in parallel through the STM and stored once the block is sealed.
+pub struct BlockProducer {
+    store: Arc<BlockStore>,
+    engine: Arc<ExecutionEngine>,
+    submissions: mpsc::Sender<Submission>,
+    /// tx_hash -> submissions of it waiting for their block
+    pending: Arc<DashMap<String, usize>>,
+    /// held by the builder from the first transaction of a block until it is
+    /// sealed, and by writes that bypass blocks, so the state root of a block
+    /// is the state its transactions left
+    window: Arc<Mutex<()>>,
+    max_txs: usize,
+}
+
+impl BlockProducer {
+    /// Starts the block builder on the current tokio runtime. Transactions
+    /// run on `engine`.
+    pub fn spawn(
+        tm: Arc<SVMMemory>,
+        svm: Arc<SVM>,
+        store: Arc<BlockStore>,
+        config: BlockConfig,
+        engine: Arc<ExecutionEngine>,
+    ) -> Self {
+        let execute: Execute = {
+            let tm = tm.clone();
+            Arc::new(move |tx_body: &TxBody| execute_tx(tx_body.clone(), tm.clone(), svm.clone()))
+        };
+        Self::spawn_with(tm, store, config, engine, execute)
+    }
+
+    /// Like `spawn`, running every transaction with `execute`.
+    pub fn spawn_with(
+        tm: Arc<SVMMemory>,
+        store: Arc<BlockStore>,
+        config: BlockConfig,
+        engine: Arc<ExecutionEngine>,
+        execute: Execute,
+    ) -> Self {
+        // every submission holds room in the engine's queue, so sending
+        // never waits
+        let (submissions, received) = mpsc::channel(engine.capacity());
+        let max_txs = config.max_txs;
+        let pending = Arc::new(DashMap::new());
+        let window = Arc::new(Mutex::new(()));
+        tokio::spawn(run_builder(
+            received,
+            tm,
+            store.clone(),
+            pending.clone(),
+            window.clone(),
+            config,
+            engine.clone(),
+            execute,
+        ));
+        Self {
+            store,
+            engine,
+            submissions,
+            pending,
+            window,
+            max_txs,
+        }
+    }
+
+    /// Waits for the block being built, then keeps the next one from
+    /// starting until the guard is dropped, for writes that do not go
+    /// through blocks.
+    pub async fn outside_blocks(&self) -> OwnedMutexGuard<()> {
+        self.window.clone().lock_owned().await
+    }
+
+    pub fn store(&self) -> &Arc<BlockStore> {
+        &self.store
+    }
+
+    /// Where `tx_hash` is, whichever connection submitted it. The receipt and
+    /// its block height come along once the transaction is in a block.
+    pub fn tx_status(&self, tx_hash: &str) -> (TxStatus, Option<(u64, Receipt)>) {
+        // blocks are stored before their transactions stop being pending
+        if self.pending.contains_key(tx_hash) {
+            return (TxStatus::Pending, None);
+        }
+        match self.store.receipt(tx_hash) {
+            Some((height, receipt)) if receipt.status => {
+                (TxStatus::Committed, Some((height, receipt)))
+            }
+            Some((height, receipt)) => (TxStatus::Failed, Some((height, receipt))),
+            None => (TxStatus::Unknown, None),
+        }
+    }
+
+    /// Queues `tx_body` for the next block and waits for its result.
+    pub async fn submit(&self, tx_body: TxBody) -> Result<TxResult, Busy> {
+        let mut results = self
+            .submit_batch(vec![tx_body], BatchMode::Independent)
+            .await?;
+        Ok(results.remove(0))
+    }
+
+    /// Queues `txs` and waits for their results, in the same order.
+    pub async fn submit_batch(
+        &self,
+        txs: Vec<TxBody>,
+        mode: BatchMode,
+    ) -> Result<Vec<TxResult>, Busy> {
+        Ok(self.enqueue(txs, mode)?.results().await)
+    }
+
+    /// Queues `txs` for the next blocks without waiting for them. In
+    /// `BatchMode::Block` the batch has to fit in one block, independent
+    /// batches are split over as many blocks as they need. Transactions
+    /// without a `tx_hash` get their canonical hash. Fails, queuing none of
+    /// them, if the engine's queue has no room for the whole batch.
+    pub fn enqueue(&self, mut txs: Vec<TxBody>, mode: BatchMode) -> Result<Queued, Busy> {
+        txs.iter_mut().for_each(TxBody::fill_hash);
+        if txs.is_empty() {
+            return Ok(Queued::failed(txs, ""));
+        }
+        if mode == BatchMode::Block && txs.len() > self.max_txs {
+            let e = format!(
+                "batch of {} txs does not fit a block of max_txs={}",
+                txs.len(),
+                self.max_txs
+            );
+            return Ok(Queued::failed(txs, &e));
+        }
+        let mut admitted = self.engine.admit(txs.len())?;
+
+        for tx_body in &txs {
+            *self.pending.entry(tx_body.tx_hash.clone()).or_default() += 1;
+        }
+        let mut parts = vec![];
+        for part in txs.chunks(mode.part_size(txs.len(), self.max_txs)) {
+            let (done, results) = oneshot::channel();
+            let submission = Submission {
+                txs: part.to_vec(),
+                mode,
+                done,
+                admitted: admitted.split(part.len()).unwrap(),
+            };
+            // the channel has a slot for every permit of the queue, so it is
+            // only ever closed, never full
+            if self.submissions.try_send(submission).is_err() {
+                for tx_body in part {
+                    settle(&self.pending, &tx_body.tx_hash);
+                }
+                parts.push((part.len(), Err("block builder stopped".to_string())));
+            } else {
+                parts.push((part.len(), Ok(results)));
+            }
+        }
+        Ok(Queued { txs, parts })
+    }
+}
+
+/// Results of one part of a batch, or why it was not queued.
+type PartResults = Result<oneshot::Receiver<Vec<TxResult>>, String>;
+
+/// Transactions taken by `BlockProducer::enqueue`.
+pub struct Queued {
+    txs: Vec<TxBody>,
+    /// results of each part of the batch sent to a block, with its size
+    parts: Vec<(usize, PartResults)>,
+}
+
+impl Queued {
+    fn failed(txs: Vec<TxBody>, e: &str) -> Self {
+        Self {
+            parts: vec![(txs.len(), Err(e.to_string()))],
+            txs,
+        }
+    }
+
+    /// The transactions, with their hashes filled in.
+    pub fn txs(&self) -> &[TxBody] {
+        &self.txs
+    }
+
+    /// Waits for the block with the transactions and returns their results,
+    /// in the order they were queued.
+    pub async fn results(self) -> Vec<TxResult> {
+        let mut all = Vec::with_capacity(self.txs.len());
+        for (len, results) in self.parts {
+            let txs = &self.txs[all.len()..all.len() + len];
+            let e = match results {
+                Ok(results) => match results.await {
+                    Ok(results) => {
+                        all.extend(results);
+                        continue;
+                    }
+                    Err(_) => "block builder dropped tx".to_string(),
+                },
+                Err(e) => e,
+            };
+            all.extend(fail_all(txs, &e));
+        }
+        all
+    }
+}
+
+fn fail_all(txs: &[TxBody], e: &str) -> Vec<TxResult> {
+    txs.iter()
+        .map(|tx_body| TxResult::from_result(tx_body, &Err(e.to_string())))
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run_builder(
+    mut received: mpsc::Receiver<Submission>,
+    tm: Arc<SVMMemory>,
+    store: Arc<BlockStore>,
+    pending: Arc<DashMap<String, usize>>,
+    window: Arc<Mutex<()>>,
+    config: BlockConfig,
+    engine: Arc<ExecutionEngine>,
+    execute: Execute,
+) {
+    // a submission that did not fit the last block starts the next one
+    let mut carried = None;
+    loop {
+        let first = match carried.take() {
+            Some(first) => first,
+            None => match received.recv().await {
+                Some(first) => first,
+                None => return,
+            },
+        };
+        let mut tx_count = first.txs.len();
+        let mut batch = vec![first];
+        let deadline = Instant::now() + config.interval;
+        while tx_count < config.max_txs {
+            match timeout_at(deadline, received.recv()).await {
+                Ok(Some(submission)) if tx_count + submission.txs.len() > config.max_txs => {
+                    carried = Some(submission);
+                    break;
+                }
+                Ok(Some(submission)) => {
+                    tx_count += submission.txs.len();
+                    batch.push(submission);
+                }
+                _ => break,
+            }
+        }
+
+        let window = window.clone().lock_owned().await;
+        let mut units = vec![];
+        let mut waiters = vec![];
+        for Submission {
+            txs,
+            mode,
+            done,
+            admitted,
+        } in batch
+        {
+            waiters.push((txs.len(), done, admitted));
+            units.extend(mode.units(txs));
+        }
+        // units run side by side on the engine, each one in its own order
+        let executed = join_all(units.iter().cloned().map(|unit| {
+            let execute = execute.clone();
+            engine.spawn(move || {
+                unit.iter()
+                    .map(|tx_body| execute_caught(&*execute, tx_body))
+                    .collect::<Vec<Executed>>()
+            })
+        }))
+        .await;
+        // transactions fail alone when they panic, a unit only fails as a
+        // whole if its job is lost; the block still seals the others
+        let results: Vec<Executed> = units
+            .iter()
+            .zip(executed)
+            .flat_map(|(unit, results)| match results {
+                Ok(results) => results,
+                Err(_) => {
+                    error!("tx execution job lost txs={}", unit.len());
+                    unit.iter()
+                        .map(|_| Executed {
+                            result: Err("tx execution panicked".to_string()),
+                            gas: 0,
+                        })
+                        .collect()
+                }
+            })
+            .collect();
+        let txs: Vec<TxBody> = units.into_iter().flatten().collect();
+
+        let sealed = {
+            let (tm, store, txs) = (tm.clone(), store.clone(), txs.clone());
+            engine
+                .run(move || {
+                    // answered only once their writes are visible, so a
+                    // sender's next read of the latest state sees them
+                    let last_seq = results
+                        .iter()
+                        .filter_map(|executed| executed.result.as_ref().ok())
+                        .map(|committed| committed.seq)
+                        .max();
+                    if let Some(seq) = last_seq {
+                        tm.wait_visible(seq);
+                    }
+                    // in submission order, the block lists them in commit order
+                    let tx_results: Vec<TxResult> = txs
+                        .iter()
+                        .zip(&results)
+                        .map(|(tx_body, executed)| executed.receipt(tx_body).to_tx_result())
+                        .collect();
+                    let block = seal_block(&store, &tm, txs, results);
+                    store_block(&store, &block).map(|()| (block, tx_results))
+                })
+                .await
+        };
+        drop(window);
+        // whether or not the block was built, none of its transactions stays
+        // pending; stored blocks come first so their status is never unknown
+        for tx_body in &txs {
+            settle(&pending, &tx_body.tx_hash);
+        }
+        let (tx_results, stored) = match sealed {
+            Ok(Ok((block, tx_results))) => {
+                info!(
+                    "sealed block height={} txs={} state_root={}",
+                    block.height,
+                    block.txs.len(),
+                    block.state_root
+                );
+                (tx_results, true)
+            }
+            // the chain cannot go on without the block, so none of its
+            // transactions is acknowledged and no further block is built
+            Ok(Err(e)) => {
+                error!(
+                    "failed to store block, stopping the block builder err={}",
+                    e
+                );
+                let e = format!("block could not be stored err={}", e);
+                (fail_all(&txs, &e), false)
+            }
+            Err(e) => {
+                error!("failed to seal block err={}", e);
+                (fail_all(&txs, &e), true)
+            }
+        };
+        let mut tx_results = tx_results.into_iter();
+        for (txs, waiter, _admitted) in waiters {
+            _ = waiter.send(tx_results.by_ref().take(txs).collect());
+        }
+        if !stored {
+            return;
+        }
+    }
+}
+
+/// Appends `block` to `store`, trying again a few times as a failed write
+/// may be transient.
+fn store_block(store: &BlockStore, block: &Block) -> io::Result<()> {
+    let mut attempt = 0;
+    loop {
+        match store.append(block.clone()) {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < STORE_RETRIES => {
+                error!(
+                    "failed to store block height={} attempt={} err={}",
+                    block.height, attempt, e
+                );
+                thread::sleep(Duration::from_millis(100 << attempt));
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Drops one pending submission of `tx_hash`.
+fn settle(pending: &DashMap<String, usize>, tx_hash: &str) {
+    pending.remove_if_mut(tx_hash, |_, count| {
+        *count -= 1;
+        *count == 0
+    });
+}
+
+/// Runs one transaction with `execute`, failing it alone if it panics. The
+/// transactions of a unit before it keep their commits, the ones after it
+/// still run.
+pub(crate) fn execute_caught<F>(execute: &F, tx_body: &TxBody) -> Executed
+where
+    F: Fn(&TxBody) -> Executed + ?Sized,
+{
+    panic::catch_unwind(AssertUnwindSafe(|| execute(tx_body))).unwrap_or_else(|_| {
+        error!("tx execution panicked tx_hash={}", tx_body.tx_hash);
+        Executed {
+            result: Err("tx execution panicked".to_string()),
+            gas: 0,
+        }
+    })
+}
+
+/// Runs `execute` over `items` on a thread per core, returning the results
+/// in input order.
+pub(crate) fn execute_batch<T, R, F>(items: &[T], execute: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let workers = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items.len());
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = thread::scope(|s| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = vec![];
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(index) else {
+                            return done;
+                        };
+                        done.push((index, execute(item)));
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Builds the next block from executed `txs`. Committed transactions go in
+/// commit order, so running them one by one reproduces the state; failed
+/// ones follow in the order they arrived.
+fn seal_block(
+    store: &BlockStore,
+    tm: &SVMMemory,
+    txs: Vec<TxBody>,
+    results: Vec<Executed>,
+) -> Block {
+    let mut executed: Vec<(TxBody, Executed)> = txs.into_iter().zip(results).collect();
+    // stable, so failed transactions keep their arrival order
+    executed.sort_by_key(|(_, executed)| match &executed.result {
+        Ok(committed) => (false, committed.seq),
+        Err(_) => (true, 0),
+    });
+
+    let (txs, receipts) = executed
+        .into_iter()
+        .map(|(tx_body, executed)| {
+            let receipt = executed.receipt(&tx_body);
+            (tx_body, receipt)
+        })
+        .unzip();
+    Block::new(
+        store.latest().as_ref(),
+        chrono::Utc::now().timestamp_millis(),
+        txs,
+        receipts,
+        hex::encode(tm.state_root()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::replay::{replay, ReplayBatch, ReplayMode};
+    use crate::executor::engine::EngineConfig;
+    use crate::svm::{object::SVMObject, primitive_types::SVMPrimitives};
+    use crate::test_support::{increment, tx};
+
+    #[test]
+    fn blocks_list_commits_in_serial_order() {
+        let tm = Arc::new(SVMMemory::new());
+        let store = BlockStore::default();
+        let txs: Vec<TxBody> = (0..64).map(tx).collect();
+
+        let results = execute_batch(&txs, |tx_body| increment(&tm, tx_body));
+        let first = seal_block(&store, &tm, txs.clone(), results);
+        store.append(first.clone()).unwrap();
+
+        assert_eq!(first.height, 1);
+        assert_eq!(first.txs.len(), 64);
+        assert_eq!(first.state_root, hex::encode(tm.state_root()));
+        // the 32 even increments committed first, each seeing the ones before it
+        let mut total = 0;
+        for (tx_body, receipt) in first.txs.iter().zip(&first.receipts).take(32) {
+            assert_eq!(receipt.tx_hash, tx_body.tx_hash);
+            let SVMPrimitives::U24(amount) = tx_body.args[0] else {
+                unreachable!()
+            };
+            total += amount;
+            assert_eq!(receipt.ret_value, Some(SVMPrimitives::U24(total)));
+            assert_eq!(receipt.gas, amount as u64);
+            assert_eq!(receipt.write_set, vec!["0xcounter".to_string()]);
+        }
+        assert!(first.receipts[32..].iter().all(|receipt| !receipt.status));
+        let failed: Vec<&str> = first.txs[32..]
+            .iter()
+            .map(|tx| tx.tx_hash.as_str())
+            .collect();
+        let odd: Vec<String> = (0..64)
+            .filter(|i| i % 2 == 1)
+            .map(|i| tx(i).tx_hash)
+            .collect();
+        assert_eq!(failed, odd);
+
+        let second = seal_block(&store, &tm, vec![tx(64)], vec![increment(&tm, &tx(64))]);
+        assert_eq!(second.height, 2);
+        assert_eq!(second.parent_hash, first.hash);
+        assert_ne!(second.state_root, first.state_root);
+    }
+
+    #[test]
+    fn block_batches_run_in_order() {
+        let tm = Arc::new(SVMMemory::new());
+        let mut units = BatchMode::Block.units((0..8).map(|i| tx(i * 2)).collect());
+        units.extend(BatchMode::Independent.units((8..16).map(|i| tx(i * 2)).collect()));
+        assert_eq!(units.len(), 9);
+
+        let results = execute_batch(&units, |unit| {
+            unit.iter()
+                .map(|tx_body| increment(&tm, tx_body))
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(results[0].len(), 8);
+        let seqs: Vec<u64> = results[0]
+            .iter()
+            .map(|executed| executed.result.as_ref().unwrap().seq)
+            .collect();
+        assert!(seqs.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[tokio::test]
+    async fn blocks_keep_to_max_txs_and_block_batches_stay_together() {
+        let tm = Arc::new(SVMMemory::new());
+        let execute: Execute = {
+            let tm = tm.clone();
+            Arc::new(move |tx_body: &TxBody| increment(&tm, tx_body))
+        };
+        let engine = Arc::new(ExecutionEngine::new(&EngineConfig::default()));
+        let config = BlockConfig {
+            max_txs: 4,
+            interval: Duration::from_millis(50),
+        };
+        let producer = BlockProducer::spawn_with(
+            tm.clone(),
+            Arc::new(BlockStore::default()),
+            config,
+            engine,
+            execute,
+        );
+
+        let independent: Vec<TxBody> = (0..6).map(|i| tx(i * 2)).collect();
+        let in_order: Vec<TxBody> = (6..9).rev().map(|i| tx(i * 2)).collect();
+        let first = producer
+            .enqueue(independent.clone(), BatchMode::Independent)
+            .unwrap();
+        let second = producer
+            .enqueue(in_order.clone(), BatchMode::Block)
+            .unwrap();
+        assert!(first.results().await.iter().all(|result| result.status));
+        let results = second.results().await;
+        assert!(results.iter().all(|result| result.status));
+
+        let store = producer.store();
+        let blocks: Vec<Block> = (1..=store.height())
+            .map(|height| store.get(height).unwrap())
+            .collect();
+        assert!(blocks.iter().all(|block| block.txs.len() <= 4));
+        assert_eq!(blocks.iter().map(|block| block.txs.len()).sum::<usize>(), 9);
+
+        // the block batch sits in one block, committed in the given order
+        let height = producer.tx_status(&in_order[0].tx_hash).1.unwrap().0;
+        let listed: Vec<&TxBody> = blocks[height as usize - 1]
+            .txs
+            .iter()
+            .filter(|tx_body| in_order.contains(tx_body))
+            .collect();
+        assert_eq!(listed, in_order.iter().collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn panicking_txs_fail_without_losing_their_block() {
+        let panicking = |tm: Arc<SVMMemory>| -> Execute {
+            Arc::new(move |tx_body: &TxBody| {
+                assert_ne!(tx_body.tx_hash, tx(4).tx_hash, "tx panicked");
+                increment(&tm, tx_body)
+            })
+        };
+        let tm = Arc::new(SVMMemory::new());
+        let execute = panicking(tm.clone());
+        let engine = Arc::new(ExecutionEngine::new(&EngineConfig::default()));
+        let producer = BlockProducer::spawn_with(
+            tm.clone(),
+            Arc::new(BlockStore::default()),
+            BlockConfig::default(),
+            engine,
+            execute,
+        );
+
+        let txs = vec![tx(2), tx(4), tx(6)];
+        let results = producer
+            .submit_batch(txs.clone(), BatchMode::Independent)
+            .await
+            .unwrap();
+        let statuses: Vec<bool> = results.iter().map(|result| result.status).collect();
+        assert_eq!(statuses, vec![true, false, true]);
+
+        let block = producer.store().latest().unwrap();
+        assert_eq!(block.txs.len(), 3);
+        assert_eq!(block.state_root, hex::encode(tm.state_root()));
+        for tx_body in &txs {
+            assert_ne!(producer.tx_status(&tx_body.tx_hash).0, TxStatus::Pending);
+        }
+        assert_eq!(producer.tx_status(&tx(4).tx_hash).0, TxStatus::Failed);
+
+        // in a block batch, the one before it keeps its commit
+        let txs = vec![tx(8), tx(4), tx(10)];
+        let results = producer.submit_batch(txs, BatchMode::Block).await.unwrap();
+        let statuses: Vec<bool> = results.iter().map(|result| result.status).collect();
+        assert_eq!(statuses, vec![true, false, true]);
+        let store = producer.store();
+        let blocks: Vec<_> = (1..=store.height())
+            .map(|height| ReplayBatch::from(store.get(height).unwrap()))
+            .collect();
+        let fresh = Arc::new(SVMMemory::new());
+        let execute = panicking(fresh.clone());
+        let report = replay(&fresh, blocks, ReplayMode::Sequential, |tx_body| {
+            execute(tx_body)
+        });
+        assert_eq!(report.divergence, None);
+        assert_eq!(report.state_root, hex::encode(tm.state_root()));
+    }
+
+    #[tokio::test]
+    async fn writes_outside_blocks_wait_for_the_block() {
+        let tm = Arc::new(SVMMemory::new());
+        let (started, running) = std::sync::mpsc::channel();
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let execute: Execute = {
+            let tm = tm.clone();
+            let started = std::sync::Mutex::new(started);
+            let released = std::sync::Mutex::new(released);
+            Arc::new(move |tx_body: &TxBody| {
+                started.lock().unwrap().send(()).unwrap();
+                released.lock().unwrap().recv().unwrap();
+                increment(&tm, tx_body)
+            })
+        };
+        let engine = Arc::new(ExecutionEngine::new(&EngineConfig::default()));
+        let producer = Arc::new(BlockProducer::spawn_with(
+            tm.clone(),
+            Arc::new(BlockStore::default()),
+            BlockConfig::default(),
+            engine,
+            execute,
+        ));
+        let queued = producer
+            .enqueue(vec![tx(2)], BatchMode::Independent)
+            .unwrap();
+        tokio::task::spawn_blocking(move || running.recv().unwrap())
+            .await
+            .unwrap();
+
+        let writer = {
+            let (producer, tm) = (producer.clone(), tm.clone());
+            tokio::spawn(async move {
+                let _window = producer.outside_blocks().await;
+                let object = SVMObject {
+                    value: SVMPrimitives::U24(7),
+                    version: 1,
+                };
+                tm.set(b"0xother".to_vec(), object).unwrap();
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!writer.is_finished());
+        release.send(()).unwrap();
+        assert!(queued.results().await[0].status);
+        writer.await.unwrap();
+
+        // the block's root has the counter alone
+        let counter = SVMMemory::new();
+        let object = SVMObject {
+            value: SVMPrimitives::U24(2),
+            version: 1,
+        };
+        counter.set(b"0xcounter".to_vec(), object).unwrap();
+        let block = producer.store().latest().unwrap();
+        assert_eq!(block.state_root, hex::encode(counter.state_root()));
+        assert_ne!(block.state_root, hex::encode(tm.state_root()));
+    }
+}
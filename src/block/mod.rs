@@ -0,0 +1,61 @@
+use crate::codec;
+use crate::executor::types::{Receipt, TxBody};
+use crate::merkle::EMPTY_HASH;
+use serde::{Deserialize, Serialize};
+
+pub mod builder;
+pub mod replay;
+pub mod store;
+pub mod stream;
+
+/// Transactions executed together, in the order their commits serialize.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Block {
+    /// first block is at height 1
+    pub height: u64,
+    /// hex hash of the previous block, zeros for the first one
+    pub parent_hash: String,
+    /// unix time in milliseconds when the block was sealed
+    pub timestamp: i64,
+    pub txs: Vec<TxBody>,
+    /// one per transaction, in the same order
+    pub receipts: Vec<Receipt>,
+    /// hex merkle root of the state after the block's transactions
+    pub state_root: String,
+    /// hex hash over every other field
+    pub hash: String,
+}
+
+impl Block {
+    pub fn new(
+        parent: Option<&Block>,
+        timestamp: i64,
+        txs: Vec<TxBody>,
+        receipts: Vec<Receipt>,
+        state_root: String,
+    ) -> Self {
+        let mut block = Block {
+            height: parent.map_or(1, |parent| parent.height + 1),
+            parent_hash: parent.map_or(hex::encode(EMPTY_HASH), |parent| parent.hash.clone()),
+            timestamp,
+            txs,
+            receipts,
+            state_root,
+            hash: String::new(),
+        };
+        block.hash = block.compute_hash();
+        block
+    }
+
+    pub fn compute_hash(&self) -> String {
+        let fields = (
+            self.height,
+            &self.parent_hash,
+            self.timestamp,
+            &self.txs,
+            &self.receipts,
+            &self.state_root,
+        );
+        hex::encode(codec::hash(&fields))
+    }
+}
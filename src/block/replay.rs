@@ -0,0 +1,268 @@
+use super::{
+    builder::{execute_batch, execute_caught},
+    Block,
+};
+use crate::block_stm::svm_memory::SVMMemory;
+use crate::executor::{
+    types::{Receipt, TxBody},
+    Executed,
+};
+use serde::Serialize;
+use std::{
+    fs,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+/// How replayed transactions are executed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReplayMode {
+    /// one at a time in log order, checking every receipt
+    #[default]
+    Sequential,
+    /// a block at a time through the STM, like the block builder; only the
+    /// state root after each block is checked, since results may depend on
+    /// the order transactions happen to commit in
+    Parallel,
+}
+
+impl FromStr for ReplayMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sequential" => Ok(Self::Sequential),
+            "parallel" => Ok(Self::Parallel),
+            _ => Err(format!("unknown replay mode={}", s)),
+        }
+    }
+}
+
+/// Transactions replayed together, with what the node recorded for them.
+pub struct ReplayBatch {
+    pub height: Option<u64>,
+    pub txs: Vec<TxBody>,
+    /// receipts, in transaction order, and hex state root after the batch
+    pub expected: Option<(Vec<Receipt>, String)>,
+}
+
+impl From<Block> for ReplayBatch {
+    fn from(block: Block) -> Self {
+        Self {
+            height: Some(block.height),
+            txs: block.txs,
+            expected: Some((block.receipts, block.state_root)),
+        }
+    }
+}
+
+/// First place the replay disagrees with the recorded history.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub height: Option<u64>,
+    /// `None` when only the state root after the block differs
+    pub tx_hash: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ReplayReport {
+    pub batches: usize,
+    pub txs: usize,
+    /// hex merkle root after the last replayed transaction
+    pub state_root: String,
+    pub divergence: Option<Divergence>,
+}
+
+/// Reads a log with one JSON `TxBody` per line. Nothing is recorded for
+/// these, so they replay as a single batch without checks.
+pub fn read_tx_log(path: &Path) -> io::Result<ReplayBatch> {
+    let mut txs = vec![];
+    for line in BufReader::new(fs::File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        txs.push(
+            serde_json::from_str(&line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        );
+    }
+    Ok(ReplayBatch {
+        height: None,
+        txs,
+        expected: None,
+    })
+}
+
+/// Re-executes `batches` on `tm`, which holds the genesis state, stopping
+/// at the first divergence from the recorded receipts or state roots.
+pub fn replay<F>(
+    tm: &SVMMemory,
+    batches: Vec<ReplayBatch>,
+    mode: ReplayMode,
+    execute: F,
+) -> ReplayReport
+where
+    F: Fn(&TxBody) -> Executed + Sync,
+{
+    // panics fail the transaction, as in the block builder
+    let run = &execute;
+    let execute = |tx_body: &TxBody| execute_caught(run, tx_body);
+    let mut report = ReplayReport {
+        batches: 0,
+        txs: 0,
+        state_root: String::new(),
+        divergence: None,
+    };
+    for batch in batches {
+        report.divergence = match mode {
+            ReplayMode::Sequential => replay_sequential(&batch, &execute, &mut report.txs),
+            ReplayMode::Parallel => {
+                execute_batch(&batch.txs, execute);
+                report.txs += batch.txs.len();
+                None
+            }
+        };
+        if report.divergence.is_none() {
+            report.divergence = check_state_root(tm, &batch);
+        }
+        if report.divergence.is_some() {
+            break;
+        }
+        report.batches += 1;
+    }
+    report.state_root = hex::encode(tm.state_root());
+    report
+}
+
+fn replay_sequential<F>(batch: &ReplayBatch, execute: &F, txs: &mut usize) -> Option<Divergence>
+where
+    F: Fn(&TxBody) -> Executed,
+{
+    for (index, tx_body) in batch.txs.iter().enumerate() {
+        let receipt = execute(tx_body).receipt(tx_body);
+        *txs += 1;
+        let Some((receipts, _)) = &batch.expected else {
+            continue;
+        };
+        if let Some(reason) = receipts
+            .get(index)
+            .and_then(|expected| diff(expected, &receipt))
+        {
+            return Some(Divergence {
+                height: batch.height,
+                tx_hash: Some(tx_body.tx_hash.clone()),
+                reason,
+            });
+        }
+    }
+    None
+}
+
+fn check_state_root(tm: &SVMMemory, batch: &ReplayBatch) -> Option<Divergence> {
+    let (_, expected) = batch.expected.as_ref()?;
+    let actual = hex::encode(tm.state_root());
+    (&actual != expected).then(|| Divergence {
+        height: batch.height,
+        tx_hash: None,
+        reason: format!("state_root={} expected={}", actual, expected),
+    })
+}
+
+/// What differs between two receipts of the same transaction. Gas and
+/// retries are left out, they depend on the run rather than the history.
+fn diff(expected: &Receipt, actual: &Receipt) -> Option<String> {
+    if expected.status != actual.status || expected.errs != actual.errs {
+        return Some(format!(
+            "status={} errs={:?} expected status={} errs={:?}",
+            actual.status, actual.errs, expected.status, expected.errs
+        ));
+    }
+    if expected.ret_value != actual.ret_value {
+        return Some(format!(
+            "ret_value={:?} expected={:?}",
+            actual.ret_value, expected.ret_value
+        ));
+    }
+    if expected.write_set != actual.write_set {
+        return Some(format!(
+            "write_set={:?} expected={:?}",
+            actual.write_set, expected.write_set
+        ));
+    }
+    if expected.write_hash != actual.write_hash {
+        return Some(format!(
+            "written values hash={} expected={}",
+            actual.write_hash, expected.write_hash
+        ));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::svm::primitive_types::SVMPrimitives;
+    use crate::test_support::{increment, tx};
+    use std::sync::Arc;
+
+    /// Two blocks of counter increments, recorded by running them in order.
+    fn history() -> Vec<Block> {
+        let tm = Arc::new(SVMMemory::new());
+        let mut blocks: Vec<Block> = vec![];
+        for txs in [
+            (0..8).map(tx).collect::<Vec<_>>(),
+            vec![tx(8), tx(9), tx(10)],
+        ] {
+            let receipts = txs
+                .iter()
+                .map(|tx_body| increment(&tm, tx_body).receipt(tx_body))
+                .collect();
+            let state_root = hex::encode(tm.state_root());
+            blocks.push(Block::new(blocks.last(), 0, txs, receipts, state_root));
+        }
+        blocks
+    }
+
+    fn run(blocks: Vec<Block>, mode: ReplayMode) -> ReplayReport {
+        let tm = Arc::new(SVMMemory::new());
+        let batches = blocks.into_iter().map(ReplayBatch::from).collect();
+        replay(&tm, batches, mode, |tx_body| increment(&tm, tx_body))
+    }
+
+    #[test]
+    fn replay_matches_history_and_finds_divergence() {
+        let blocks = history();
+        for mode in [ReplayMode::Sequential, ReplayMode::Parallel] {
+            let report = run(blocks.clone(), mode);
+            assert_eq!(report.divergence, None, "mode={:?}", mode);
+            assert_eq!(report.batches, 2);
+            assert_eq!(report.txs, 11);
+            assert_eq!(report.state_root, blocks[1].state_root);
+        }
+
+        let mut tampered = blocks.clone();
+        tampered[1].receipts[1].ret_value = Some(SVMPrimitives::U24(0));
+        let report = run(tampered, ReplayMode::Sequential);
+        let divergence = report.divergence.unwrap();
+        assert_eq!(divergence.height, Some(2));
+        assert_eq!(divergence.tx_hash, Some(tx(9).tx_hash));
+        assert_eq!(report.batches, 1);
+        assert_eq!(report.txs, 10);
+
+        // same result and keys, other values written
+        let mut tampered = blocks.clone();
+        tampered[0].receipts[2].write_hash = "00".repeat(32);
+        let divergence = run(tampered, ReplayMode::Sequential).divergence.unwrap();
+        assert_eq!(divergence.tx_hash, Some(tx(2).tx_hash));
+        assert!(divergence.reason.starts_with("written values hash="));
+
+        let mut tampered = blocks;
+        tampered[0].state_root = "00".repeat(32);
+        let report = run(tampered, ReplayMode::Parallel);
+        assert_eq!(report.divergence.unwrap().tx_hash, None);
+        assert_eq!(report.batches, 0);
+    }
+}
@@ -0,0 +1,220 @@
+use super::Block;
+use crate::executor::types::Receipt;
+use crate::storage::{
+    file::to_io_error,
+    record_log::{read_file, RecordLog},
+};
+use std::{collections::HashMap, io, path::Path, sync::RwLock};
+use tokio::sync::watch;
+
+pub const BLOCKS_FILE: &str = "blocks.log";
+
+#[derive(Default)]
+struct Chain {
+    blocks: Vec<Block>,
+    /// tx_hash -> height and position of its latest receipt
+    receipts: HashMap<String, (u64, usize)>,
+    /// number of transactions in all blocks before each block
+    tx_offsets: Vec<u64>,
+}
+
+impl Chain {
+    fn push(&mut self, block: Block) {
+        for (index, receipt) in block.receipts.iter().enumerate() {
+            self.receipts
+                .insert(receipt.tx_hash.clone(), (block.height, index));
+        }
+        self.tx_offsets.push(self.tx_count());
+        self.blocks.push(block);
+    }
+
+    fn tx_count(&self) -> u64 {
+        match (self.tx_offsets.last(), self.blocks.last()) {
+            (Some(offset), Some(block)) => offset + block.txs.len() as u64,
+            _ => 0,
+        }
+    }
+}
+
+/// Reads every block of a block log, e.g. a copy taken from a node, failing
+/// on any torn or corrupt record.
+pub fn read_blocks(path: &Path) -> io::Result<Vec<Block>> {
+    read_file(path)?
+        .iter()
+        .map(|record| bincode::deserialize(record).map_err(to_io_error))
+        .collect()
+}
+
+/// Every produced block, by height, with the receipts of their transactions
+/// indexed by `tx_hash`. Kept in memory and, when opened on a file, appended
+/// to it as well.
+///
+/// Transactions are also numbered in chain order, from 0, so a stream of
+/// them can resume at any one.
+pub struct BlockStore {
+    chain: RwLock<Chain>,
+    log: Option<RecordLog>,
+    /// fsync every block instead of only handing it to the OS
+    sync: bool,
+    /// height of the latest block
+    height: watch::Sender<u64>,
+}
+
+impl Default for BlockStore {
+    fn default() -> Self {
+        Self {
+            chain: RwLock::default(),
+            log: None,
+            sync: false,
+            height: watch::Sender::new(0),
+        }
+    }
+}
+
+impl BlockStore {
+    /// Opens or creates the block log at `path`, loading every block in it.
+    pub fn open(path: &Path, sync: bool) -> io::Result<Self> {
+        let (log, records) = RecordLog::open(path)?;
+        let mut chain = Chain::default();
+        for record in records {
+            chain.push(bincode::deserialize(&record).map_err(to_io_error)?);
+        }
+        Ok(Self {
+            height: watch::Sender::new(chain.blocks.len() as u64),
+            chain: RwLock::new(chain),
+            log: Some(log),
+            sync,
+        })
+    }
+
+    /// Adds the next block, which must sit right on top of the latest one.
+    pub fn append(&self, block: Block) -> io::Result<()> {
+        let mut chain = self.chain.write().unwrap();
+        let parent_hash = chain.blocks.last().map(|parent| parent.hash.as_str());
+        if block.height != chain.blocks.len() as u64 + 1
+            || parent_hash.is_some_and(|hash| hash != block.parent_hash)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("block height={} does not extend the chain", block.height),
+            ));
+        }
+        if let Some(log) = &self.log {
+            log.append(&bincode::serialize(&block).map_err(to_io_error)?, self.sync)?;
+        }
+        let height = block.height;
+        chain.push(block);
+        self.height.send_replace(height);
+        Ok(())
+    }
+
+    pub fn get(&self, height: u64) -> Option<Block> {
+        let index = height.checked_sub(1)? as usize;
+        self.chain.read().unwrap().blocks.get(index).cloned()
+    }
+
+    pub fn latest(&self) -> Option<Block> {
+        self.chain.read().unwrap().blocks.last().cloned()
+    }
+
+    /// Height of the latest block, 0 before the first one.
+    pub fn height(&self) -> u64 {
+        self.chain.read().unwrap().blocks.len() as u64
+    }
+
+    /// Block at `height` with the number of its first transaction.
+    pub fn get_with_tx_seq(&self, height: u64) -> Option<(Block, u64)> {
+        let index = height.checked_sub(1)? as usize;
+        let chain = self.chain.read().unwrap();
+        Some((chain.blocks.get(index)?.clone(), chain.tx_offsets[index]))
+    }
+
+    /// Height of the block holding transaction number `tx_seq`, or the next
+    /// height if it is not in a block yet.
+    pub fn height_of_tx(&self, tx_seq: u64) -> u64 {
+        let chain = self.chain.read().unwrap();
+        if tx_seq >= chain.tx_count() {
+            return chain.blocks.len() as u64 + 1;
+        }
+        // the last block starting at or before tx_seq holds it
+        chain.tx_offsets.partition_point(|offset| *offset <= tx_seq) as u64
+    }
+
+    /// Changes whenever a block is appended, to the new height.
+    pub fn watch(&self) -> watch::Receiver<u64> {
+        self.height.subscribe()
+    }
+
+    /// Latest receipt of `tx_hash` with the height of its block.
+    pub fn receipt(&self, tx_hash: &str) -> Option<(u64, Receipt)> {
+        let chain = self.chain.read().unwrap();
+        let (height, index) = *chain.receipts.get(tx_hash)?;
+        let receipt = chain.blocks[height as usize - 1].receipts[index].clone();
+        Some((height, receipt))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::types::TxBody;
+    use crate::svm::primitive_types::SVMPrimitives;
+
+    fn block(parent: Option<&Block>, tx_hash: &str) -> Block {
+        let tx = TxBody {
+            tx_hash: tx_hash.to_string(),
+            code_hash: "0xtransfer".to_string(),
+            objs: vec!["0x1".to_string(), "0x2".to_string()],
+            args: vec![SVMPrimitives::U24(1)],
+        };
+        let receipt = Receipt {
+            tx_hash: tx.tx_hash.clone(),
+            code_hash: tx.code_hash.clone(),
+            status: true,
+            ret_value: Some(SVMPrimitives::Era),
+            errs: None,
+            gas: 42,
+            retries: 1,
+            read_set: tx.objs.clone(),
+            write_set: tx.objs.clone(),
+            write_hash: "00".repeat(32),
+        };
+        Block::new(parent, 0, vec![tx], vec![receipt], "00".repeat(32))
+    }
+
+    #[test]
+    fn blocks_chain_and_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(BLOCKS_FILE);
+
+        let store = BlockStore::open(&path, false).unwrap();
+        let first = block(None, "0xa");
+        let second = block(Some(&first), "0xb");
+        assert_eq!(second.height, 2);
+        assert_eq!(second.parent_hash, first.hash);
+        assert_ne!(second.hash, first.hash);
+
+        store.append(first.clone()).unwrap();
+        assert!(store.append(block(None, "0xc")).is_err());
+        store.append(second.clone()).unwrap();
+
+        let reopened = BlockStore::open(&path, false).unwrap();
+        assert_eq!(reopened.height(), 2);
+        assert_eq!(reopened.get(1), Some(first));
+        assert_eq!(reopened.latest(), Some(second.clone()));
+        assert_eq!(reopened.get(0), None);
+        assert_eq!(reopened.get(3), None);
+        assert_eq!(second.compute_hash(), second.hash);
+
+        let (height, receipt) = reopened.receipt("0xb").unwrap();
+        assert_eq!(height, 2);
+        assert_eq!(receipt, second.receipts[0]);
+        assert_eq!(reopened.receipt("0xc"), None);
+
+        assert_eq!(*reopened.watch().borrow(), 2);
+        assert_eq!(reopened.get_with_tx_seq(2), Some((second, 1)));
+        assert_eq!(reopened.height_of_tx(0), 1);
+        assert_eq!(reopened.height_of_tx(1), 2);
+        assert_eq!(reopened.height_of_tx(7), 3);
+    }
+}
@@ -0,0 +1,137 @@
+use super::{store::BlockStore, Block};
+use crate::executor::types::{Receipt, TxBody, TxStatus};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+
+/// Which transactions a transaction stream carries. Every field that is set
+/// has to match.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TxFilter {
+    #[serde(default)]
+    pub code_hash: Option<String>,
+    /// an object the transaction declared, read or wrote
+    #[serde(default)]
+    pub obj: Option<String>,
+    /// `committed` or `failed`
+    #[serde(default)]
+    pub status: Option<TxStatus>,
+}
+
+impl TxFilter {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.status {
+            None | Some(TxStatus::Committed) | Some(TxStatus::Failed) => Ok(()),
+            Some(status) => Err(format!("status={:?} never appears in a block", status)),
+        }
+    }
+
+    pub fn matches(&self, tx_body: &TxBody, receipt: &Receipt) -> bool {
+        if self
+            .code_hash
+            .as_ref()
+            .is_some_and(|code_hash| *code_hash != receipt.code_hash)
+        {
+            return false;
+        }
+        if let Some(obj) = &self.obj {
+            let touched = tx_body.objs.contains(obj)
+                || receipt.read_set.contains(obj)
+                || receipt.write_set.contains(obj);
+            if !touched {
+                return false;
+            }
+        }
+        match self.status {
+            Some(TxStatus::Committed) => receipt.status,
+            Some(TxStatus::Failed) => !receipt.status,
+            _ => true,
+        }
+    }
+}
+
+/// Calls `f` with every block of `store` from `from_height` on, and the
+/// number of its first transaction, waiting for new blocks once it reaches
+/// the latest one. Blocks are never skipped, however slowly `f` runs. Stops
+/// when `f` returns false.
+pub async fn follow<F, Fut>(store: &BlockStore, from_height: u64, mut f: F)
+where
+    F: FnMut(Block, u64) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    // taken before reading, so a block appended in between still wakes us
+    let mut heights = store.watch();
+    let mut next = from_height.max(1);
+    loop {
+        while let Some((block, tx_seq)) = store.get_with_tx_seq(next) {
+            if !f(block, tx_seq).await {
+                return;
+            }
+            next += 1;
+        }
+        if heights.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_stm::svm_memory::SVMMemory;
+    use crate::test_support::{increment, tx};
+    use std::sync::Arc;
+
+    fn block(tm: &Arc<SVMMemory>, parent: Option<&Block>, txs: Vec<TxBody>) -> Block {
+        let receipts = txs
+            .iter()
+            .map(|tx_body| increment(tm, tx_body).receipt(tx_body))
+            .collect();
+        Block::new(parent, 0, txs, receipts, hex::encode(tm.state_root()))
+    }
+
+    #[tokio::test]
+    async fn streams_resume_without_gaps() {
+        let tm = Arc::new(SVMMemory::new());
+        let store = Arc::new(BlockStore::default());
+        let first = block(&tm, None, vec![tx(0), tx(1), tx(2)]);
+        let second = block(&tm, Some(&first), vec![tx(3)]);
+        store.append(first).unwrap();
+        store.append(second.clone()).unwrap();
+
+        // odd amounts fail, see `increment`
+        let filter = TxFilter {
+            status: Some(TxStatus::Committed),
+            ..Default::default()
+        };
+        assert!(filter.validate().is_ok());
+        let pending = TxFilter {
+            status: Some(TxStatus::Pending),
+            ..Default::default()
+        };
+        assert!(pending.validate().is_err());
+
+        let appender = {
+            let store = store.clone();
+            tokio::spawn(async move {
+                let third = block(&tm, Some(&second), vec![tx(4), tx(5)]);
+                store.append(third).unwrap();
+            })
+        };
+
+        let mut seen = vec![];
+        follow(&store, store.height_of_tx(1), |block, tx_seq| {
+            for (index, (tx_body, receipt)) in block.txs.iter().zip(&block.receipts).enumerate() {
+                let tx_seq = tx_seq + index as u64;
+                if tx_seq >= 1 && filter.matches(tx_body, receipt) {
+                    seen.push((block.height, tx_seq));
+                }
+            }
+            let done = block.height == 3;
+            async move { !done }
+        })
+        .await;
+        appender.await.unwrap();
+
+        assert_eq!(seen, vec![(1, 2), (3, 4)]);
+    }
+}
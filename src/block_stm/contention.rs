@@ -0,0 +1,60 @@
+use super::svm_memory::AbortReason;
+use crate::svm::object::Version;
+use dashmap::DashMap;
+use serde::Serialize;
+
+/// Abort counters of one key.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct KeyContention {
+    pub aborts: u64,
+    pub lock_busy: u64,
+    pub version_conflicts: u64,
+    pub phantoms: u64,
+    /// versions seen by the latest version conflict on this key
+    pub last_expected_version: Option<Version>,
+    pub last_actual_version: Option<Version>,
+}
+
+/// Per-key abort counters, aggregated over every retried commit.
+#[derive(Default)]
+pub struct ContentionStats {
+    keys: DashMap<Vec<u8>, KeyContention>,
+}
+
+impl ContentionStats {
+    pub fn record(&self, reason: &AbortReason) {
+        if reason.key().is_empty() {
+            return;
+        }
+        let mut entry = self.keys.entry(reason.key().to_vec()).or_default();
+        entry.aborts += 1;
+        match reason {
+            AbortReason::LockBusy { .. } => entry.lock_busy += 1,
+            AbortReason::VersionConflict {
+                expected, actual, ..
+            } => {
+                entry.version_conflicts += 1;
+                entry.last_expected_version = Some(*expected);
+                entry.last_actual_version = Some(*actual);
+            }
+            AbortReason::Phantom { .. } => entry.phantoms += 1,
+            AbortReason::Durability { .. } => {}
+        }
+    }
+
+    /// The `limit` keys that caused the most aborts, most contended first.
+    pub fn top(&self, limit: usize) -> Vec<(Vec<u8>, KeyContention)> {
+        let mut keys: Vec<_> = self
+            .keys
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        keys.sort_by(|a, b| b.1.aborts.cmp(&a.1.aborts).then_with(|| a.0.cmp(&b.0)));
+        keys.truncate(limit);
+        keys
+    }
+
+    pub fn reset(&self) {
+        self.keys.clear();
+    }
+}
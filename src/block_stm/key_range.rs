@@ -0,0 +1,108 @@
+use std::ops::Bound;
+
+/// Range of raw keys in byte order, used for prefix and range scans.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyRange {
+    pub start: Bound<Vec<u8>>,
+    pub end: Bound<Vec<u8>>,
+}
+
+impl KeyRange {
+    pub fn all() -> Self {
+        Self {
+            start: Bound::Unbounded,
+            end: Bound::Unbounded,
+        }
+    }
+
+    /// Every key starting with `prefix`.
+    pub fn prefix(prefix: &[u8]) -> Self {
+        Self {
+            start: Bound::Included(prefix.to_vec()),
+            end: match prefix_successor(prefix) {
+                Some(successor) => Bound::Excluded(successor),
+                None => Bound::Unbounded,
+            },
+        }
+    }
+
+    /// Keys in `[start, end)`, either side open if `None`.
+    pub fn between(start: Option<Vec<u8>>, end: Option<Vec<u8>>) -> Self {
+        Self {
+            start: start.map_or(Bound::Unbounded, Bound::Included),
+            end: end.map_or(Bound::Unbounded, Bound::Excluded),
+        }
+    }
+
+    /// The part of this range strictly after `cursor`, for the next page of a scan.
+    pub fn after(&self, cursor: &[u8]) -> Self {
+        let start = match &self.start {
+            Bound::Included(start) | Bound::Excluded(start) if start.as_slice() > cursor => {
+                self.start.clone()
+            }
+            _ => Bound::Excluded(cursor.to_vec()),
+        };
+        Self {
+            start,
+            end: self.end.clone(),
+        }
+    }
+
+    /// The part of this range up to and including `last`.
+    pub fn up_to(&self, last: &[u8]) -> Self {
+        Self {
+            start: self.start.clone(),
+            end: Bound::Included(last.to_vec()),
+        }
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        let after_start = match &self.start {
+            Bound::Included(start) => key >= start.as_slice(),
+            Bound::Excluded(start) => key > start.as_slice(),
+            Bound::Unbounded => true,
+        };
+        let before_end = match &self.end {
+            Bound::Included(end) => key <= end.as_slice(),
+            Bound::Excluded(end) => key < end.as_slice(),
+            Bound::Unbounded => true,
+        };
+        after_start && before_end
+    }
+
+    /// Whether `BTreeSet::range` accepts these bounds without panicking.
+    pub fn is_valid(&self) -> bool {
+        match (&self.start, &self.end) {
+            (Bound::Excluded(start), Bound::Excluded(end)) => start < end,
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) => start <= end,
+            _ => true,
+        }
+    }
+
+    pub fn bounds(&self) -> (Bound<&[u8]>, Bound<&[u8]>) {
+        (as_slice(&self.start), as_slice(&self.end))
+    }
+}
+
+fn as_slice(bound: &Bound<Vec<u8>>) -> Bound<&[u8]> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.as_slice()),
+        Bound::Excluded(key) => Bound::Excluded(key.as_slice()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Smallest key greater than every key starting with `prefix`.
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut successor = prefix.to_vec();
+    while let Some(last) = successor.pop() {
+        if last < u8::MAX {
+            successor.push(last + 1);
+            return Some(successor);
+        }
+    }
+    None
+}
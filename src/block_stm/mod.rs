@@ -1,19 +1,91 @@
-use crate::svm::primitive_types::SVMPrimitives;
-use std::sync::Arc;
-use svm_memory::{retry_transaction, SVMMemory};
+use crate::svm::{object::SVMObject, primitive_types::SVMPrimitives};
+use key_range::KeyRange;
+use log::info;
+use std::{sync::Arc, time::Duration};
+use svm_memory::{CommitSeq, ReadOnlyTransaction, SVMMemory};
 
+pub mod contention;
+pub mod key_range;
+pub mod subscriptions;
 pub mod svm_memory;
 
+/// Most objects a single scan returns.
+pub const MAX_SCAN_LIMIT: usize = 1_000;
+
 pub fn get_val(tm: Arc<SVMMemory>, key: String) -> Option<SVMPrimitives> {
-    let key_vec = key.clone().as_bytes().to_vec();
-    match retry_transaction(tm.clone(), |txn| {
-        let return_value = match txn.read(key_vec.clone()) {
-            Some(value) => value,
-            None => return Err(format!("key={} does not exist", key)),
-        };
-        Ok(return_value)
-    }) {
-        Ok(val) => Some(val),
-        Err(_) => None,
+    ReadOnlyTransaction::new(&tm).read(key.as_bytes().to_vec())
+}
+
+/// Reads `key` as it was right after commit `at`.
+pub fn get_val_at(
+    tm: Arc<SVMMemory>,
+    key: String,
+    at: CommitSeq,
+) -> Result<Option<SVMPrimitives>, String> {
+    Ok(ReadOnlyTransaction::at(&tm, at)?.read(key.as_bytes().to_vec()))
+}
+
+/// Reads all `keys` from one snapshot, at commit `at` or the latest state.
+/// Returns the sequence the values were read at.
+pub fn get_vals(
+    tm: Arc<SVMMemory>,
+    keys: &[String],
+    at: Option<CommitSeq>,
+) -> Result<(CommitSeq, Vec<Option<SVMPrimitives>>), String> {
+    let txn = match at {
+        Some(at) => ReadOnlyTransaction::at(&tm, at)?,
+        None => ReadOnlyTransaction::new(&tm),
+    };
+    let keys: Vec<Vec<u8>> = keys.iter().map(|key| key.as_bytes().to_vec()).collect();
+    Ok((txn.seq(), txn.read_many(&keys)))
+}
+
+/// One page of a key scan.
+pub struct ScanPage {
+    /// sequence the page was read at, pass it back as `at` to page consistently
+    pub seq: CommitSeq,
+    pub entries: Vec<(Vec<u8>, SVMObject<SVMPrimitives>)>,
+    /// key to continue after, `None` once the range is exhausted
+    pub next_cursor: Option<Vec<u8>>,
+}
+
+/// Reads a page of `range`, starting after `cursor` if given.
+pub fn scan_vals(
+    tm: Arc<SVMMemory>,
+    range: &KeyRange,
+    cursor: Option<&[u8]>,
+    limit: usize,
+    at: Option<CommitSeq>,
+) -> Result<ScanPage, String> {
+    let txn = match at {
+        Some(at) => ReadOnlyTransaction::at(&tm, at)?,
+        None => ReadOnlyTransaction::new(&tm),
+    };
+    let range = match cursor {
+        Some(cursor) => range.after(cursor),
+        None => range.clone(),
+    };
+    let limit = limit.clamp(1, MAX_SCAN_LIMIT);
+    let entries = txn.scan(&range, limit);
+    let next_cursor = match entries.last() {
+        Some((last, _)) if entries.len() == limit => Some(last.clone()),
+        _ => None,
+    };
+    Ok(ScanPage {
+        seq: txn.seq(),
+        entries,
+        next_cursor,
+    })
+}
+
+/// Periodically collects object versions that fell out of the history window.
+pub async fn run_gc(tm: Arc<SVMMemory>, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    loop {
+        interval.tick().await;
+        let tm = tm.clone();
+        if let Ok(horizon) = tokio::task::spawn_blocking(move || tm.collect_garbage()).await {
+            info!("collected history below seq={}", horizon);
+        }
     }
 }
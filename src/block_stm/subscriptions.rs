@@ -0,0 +1,203 @@
+use super::svm_memory::CommitSeq;
+use crate::storage::StoredObject;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// Commits buffered per subscriber before it counts as lagging.
+pub const SUBSCRIPTION_BUFFER: usize = 1024;
+
+/// Writes of one commit that match a subscription, in key order.
+#[derive(Clone, Debug)]
+pub struct CommitChanges {
+    pub seq: CommitSeq,
+    pub objects: Vec<StoredObject>,
+}
+
+/// Keys, and key prefixes, a subscriber is told about.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    pub keys: HashSet<Vec<u8>>,
+    pub prefixes: Vec<Vec<u8>>,
+}
+
+impl Filter {
+    pub fn matches(&self, key: &[u8]) -> bool {
+        self.keys.contains(key) || self.prefixes.iter().any(|prefix| key.starts_with(prefix))
+    }
+}
+
+struct Subscriber {
+    filter: Filter,
+    sender: mpsc::Sender<CommitChanges>,
+    lagged: Arc<AtomicBool>,
+}
+
+/// Receiving end of a subscription. `changes` closes once the subscription
+/// is dropped, by `Subscriptions::unsubscribe` or because it lagged.
+pub struct Subscription {
+    pub id: u64,
+    pub changes: mpsc::Receiver<CommitChanges>,
+    lagged: Arc<AtomicBool>,
+}
+
+impl Subscription {
+    /// Whether the subscription was dropped for falling behind, so changes
+    /// after the last one received were missed.
+    pub fn lagged(&self) -> bool {
+        self.lagged.load(Ordering::Acquire)
+    }
+}
+
+/// Hands the writes of every commit to the subscribers whose filter they
+/// match. A subscriber whose buffer is full is dropped instead of slowing
+/// commits down: it still receives what was buffered, then its changes close
+/// with `lagged` set.
+#[derive(Default)]
+pub struct Subscriptions {
+    next_id: AtomicU64,
+    subscribers: RwLock<HashMap<u64, Subscriber>>,
+}
+
+impl Subscriptions {
+    pub fn subscribe(&self, filter: Filter) -> Subscription {
+        self.subscribe_with_buffer(filter, SUBSCRIPTION_BUFFER)
+    }
+
+    /// Subscribes with room for `buffer` commits not yet received.
+    pub fn subscribe_with_buffer(&self, filter: Filter, buffer: usize) -> Subscription {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let (sender, changes) = mpsc::channel(buffer.max(1));
+        let lagged = Arc::new(AtomicBool::new(false));
+        self.subscribers.write().unwrap().insert(
+            id,
+            Subscriber {
+                filter,
+                sender,
+                lagged: lagged.clone(),
+            },
+        );
+        Subscription {
+            id,
+            changes,
+            lagged,
+        }
+    }
+
+    /// Returns whether `id` was subscribed.
+    pub fn unsubscribe(&self, id: u64) -> bool {
+        self.subscribers.write().unwrap().remove(&id).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscribers.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Called with the writes of commit `seq`, in commit order.
+    pub(crate) fn publish(&self, seq: CommitSeq, writes: &[StoredObject]) {
+        let subscribers = self.subscribers.read().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        let mut dropped = vec![];
+        for (id, subscriber) in subscribers.iter() {
+            let mut objects: Vec<StoredObject> = writes
+                .iter()
+                .filter(|(key, _)| subscriber.filter.matches(key))
+                .cloned()
+                .collect();
+            if objects.is_empty() {
+                continue;
+            }
+            objects.sort_by(|a, b| a.0.cmp(&b.0));
+            match subscriber.sender.try_send(CommitChanges { seq, objects }) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    subscriber.lagged.store(true, Ordering::Release);
+                    dropped.push(*id);
+                }
+                Err(TrySendError::Closed(_)) => dropped.push(*id),
+            }
+        }
+        drop(subscribers);
+
+        if !dropped.is_empty() {
+            let mut subscribers = self.subscribers.write().unwrap();
+            for id in dropped {
+                subscribers.remove(&id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_stm::svm_memory::{retry_transaction, SVMMemory};
+    use crate::svm::primitive_types::SVMPrimitives;
+
+    fn write(tm: &Arc<SVMMemory>, keys: &[&str], value: u32) {
+        retry_transaction(tm.clone(), |txn| {
+            for key in keys {
+                txn.write(key.as_bytes().to_vec(), SVMPrimitives::U24(value));
+            }
+            Ok(SVMPrimitives::Era)
+        })
+        .unwrap();
+    }
+
+    fn keys(changes: &CommitChanges) -> Vec<&[u8]> {
+        changes
+            .objects
+            .iter()
+            .map(|(key, _)| key.as_slice())
+            .collect()
+    }
+
+    #[test]
+    fn subscribers_get_matching_writes_and_lag_out() {
+        let tm = Arc::new(SVMMemory::new());
+        let filter = Filter {
+            keys: HashSet::from([b"0xpot".to_vec()]),
+            prefixes: vec![b"0xduangua/".to_vec()],
+        };
+        let mut subscription = tm.subscriptions().subscribe(filter.clone());
+        let mut slow = tm.subscriptions().subscribe_with_buffer(filter, 2);
+
+        write(&tm, &["0xpot", "0xother"], 1);
+        write(&tm, &["0xother"], 2);
+        write(&tm, &["0xduangua/1", "0xduangua/2", "0xpotato"], 3);
+
+        let first = subscription.changes.try_recv().unwrap();
+        assert_eq!(keys(&first), vec![b"0xpot".as_slice()]);
+        assert_eq!(first.objects[0].1.value, SVMPrimitives::U24(1));
+        let second = subscription.changes.try_recv().unwrap();
+        assert!(second.seq > first.seq);
+        assert_eq!(
+            keys(&second),
+            vec![b"0xduangua/1".as_slice(), b"0xduangua/2".as_slice()]
+        );
+        assert!(subscription.changes.try_recv().is_err());
+
+        // the third matching commit does not fit the slow subscriber's buffer
+        write(&tm, &["0xpot"], 4);
+        assert_eq!(tm.subscriptions().len(), 1);
+        assert!(slow.changes.try_recv().is_ok());
+        assert!(slow.changes.try_recv().is_ok());
+        assert!(slow.changes.try_recv().is_err());
+        assert!(slow.lagged());
+        assert!(!subscription.lagged());
+
+        assert!(tm.subscriptions().unsubscribe(subscription.id));
+        assert!(tm.subscriptions().is_empty());
+    }
+}
@@ -1,47 +1,836 @@
+use super::{contention::ContentionStats, key_range::KeyRange, subscriptions::Subscriptions};
+use crate::executor::types::TxBody;
+use crate::merkle::{leaf_hash, proof::StateProof, tree::StateTree, writes_hash, Hash};
+use crate::storage::{
+    wal::{Wal, WalRecord},
+    Storage, StoredObject,
+};
 use crate::svm::{
     object::{SVMObject, Version},
     primitive_types::SVMPrimitives,
 };
-use dashmap::DashMap;
-use std::{collections::HashMap, sync::Arc, thread::sleep, time::Duration};
+use dashmap::{mapref::entry::Entry, DashMap};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet, HashMap},
+    fmt,
+    hash::{Hash as _, Hasher},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex, RwLock,
+    },
+    thread::sleep,
+    time::Duration,
+};
 use tokio::time::Instant;
 
+/// How concurrent transactions over the same objects are kept apart.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConcurrencyMode {
+    /// execute without locks, validate the read set on commit and re-run on conflicts
+    #[default]
+    Optimistic,
+    /// lock the declared objects in key order before executing, so execution never repeats
+    Locking,
+}
+
+impl FromStr for ConcurrencyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "optimistic" => Ok(Self::Optimistic),
+            "locking" => Ok(Self::Locking),
+            other => Err(format!("unknown concurrency mode={}", other)),
+        }
+    }
+}
+
+/// Why a commit was rejected, always naming the key that caused it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AbortReason {
+    /// another transaction was committing or holding the key
+    LockBusy { key: Vec<u8> },
+    /// the key was overwritten after this transaction read it
+    VersionConflict {
+        key: Vec<u8>,
+        expected: Version,
+        actual: Version,
+    },
+    /// the key appeared in a range this transaction scanned
+    Phantom { key: Vec<u8> },
+    /// the commit could not be written to the write-ahead log or the storage
+    Durability { error: String },
+}
+
+impl AbortReason {
+    /// The key that caused the abort, empty if no key was at fault.
+    pub fn key(&self) -> &[u8] {
+        match self {
+            AbortReason::LockBusy { key }
+            | AbortReason::VersionConflict { key, .. }
+            | AbortReason::Phantom { key } => key,
+            AbortReason::Durability { .. } => &[],
+        }
+    }
+
+    /// Whether running the transaction again can succeed.
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, AbortReason::Durability { .. })
+    }
+}
+
+impl fmt::Display for AbortReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AbortReason::LockBusy { key } => {
+                write!(f, "lock busy key={}", String::from_utf8_lossy(key))
+            }
+            AbortReason::VersionConflict {
+                key,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "version conflict key={} expected={} actual={}",
+                String::from_utf8_lossy(key),
+                expected,
+                actual
+            ),
+            AbortReason::Phantom { key } => {
+                write!(f, "phantom key={}", String::from_utf8_lossy(key))
+            }
+            AbortReason::Durability { error } => write!(f, "durability error={}", error),
+        }
+    }
+}
+
+enum LockState {
+    Acquired,
+    AlreadyHeld,
+    Busy,
+}
+
+/// Commit sequence number, every successful commit gets the next one.
+/// Sequence 0 is the empty state before the first commit.
+pub type CommitSeq = u64;
+
+/// Number of commits of history kept behind the latest state by default.
+pub const DEFAULT_HISTORY_RETENTION: u64 = 100_000;
+
+/// Condvars the keys waited on in `lock_keys` are spread over.
+const LOCK_WAIT_STRIPES: usize = 64;
+
+/// Every version of one object still within the GC horizon, oldest first.
+#[derive(Default)]
+struct ObjectVersions(Vec<(CommitSeq, SVMObject<SVMPrimitives>)>);
+
+impl ObjectVersions {
+    fn latest(&self) -> Option<&SVMObject<SVMPrimitives>> {
+        self.0.last().map(|(_, object)| object)
+    }
+
+    fn at(&self, seq: CommitSeq) -> Option<&SVMObject<SVMPrimitives>> {
+        self.0
+            .iter()
+            .rev()
+            .find(|(committed_at, _)| *committed_at <= seq)
+            .map(|(_, object)| object)
+    }
+
+    /// Adds the version written at `seq`, kept in commit order even when
+    /// commits apply out of it.
+    fn push(&mut self, seq: CommitSeq, object: SVMObject<SVMPrimitives>) {
+        let at = self
+            .0
+            .partition_point(|(committed_at, _)| *committed_at <= seq);
+        self.0.insert(at, (seq, object));
+    }
+
+    /// Drops versions nobody can read anymore, keeping the one visible at `horizon`.
+    fn prune(&mut self, horizon: CommitSeq) {
+        let visible = self.0.iter().rposition(|(seq, _)| *seq <= horizon);
+        if let Some(visible) = visible {
+            self.0.drain(..visible);
+        }
+    }
+}
+
+#[derive(Default)]
+struct ClockState {
+    next: CommitSeq,
+    in_flight: BTreeSet<CommitSeq>,
+    /// writes of applied commits that are not stable yet
+    unpublished: BTreeMap<CommitSeq, Vec<StoredObject>>,
+    /// writes of stable commits waiting for subscribers, in commit order
+    ready: Vec<(CommitSeq, Vec<StoredObject>)>,
+    /// whether a committer is handing `ready` writes to subscribers; the
+    /// others leave theirs to it, so they go out in order
+    publishing: bool,
+}
+
+/// Hands out commit sequence numbers and tracks the highest one below which
+/// every commit has finished writing, which is what snapshots read at.
+#[derive(Default)]
+struct CommitClock {
+    state: Mutex<ClockState>,
+    stable: AtomicU64,
+    /// notified whenever `stable` moves
+    advanced: Condvar,
+}
+
+impl CommitClock {
+    fn begin(&self) -> CommitSeq {
+        let mut state = self.state.lock().unwrap();
+        state.next += 1;
+        let seq = state.next;
+        state.in_flight.insert(seq);
+        seq
+    }
+
+    /// Makes sure sequences handed out from now on come after `seq`.
+    fn advance_past(&self, seq: CommitSeq) {
+        let mut state = self.state.lock().unwrap();
+        if state.next < seq {
+            state.next = seq;
+            if state.in_flight.is_empty() {
+                self.stable.store(seq, Ordering::Release);
+                self.advanced.notify_all();
+            }
+        }
+    }
+
+    /// Keeps the writes of `seq` until every commit before it has ended.
+    fn stage(&self, seq: CommitSeq, writes: Vec<StoredObject>) {
+        self.state.lock().unwrap().unpublished.insert(seq, writes);
+    }
+
+    /// Ends `seq`, queueing the staged writes that became stable. Returns
+    /// whether the caller is to publish them, see [`CommitClock::take_ready`].
+    fn end(&self, seq: CommitSeq) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight.remove(&seq);
+        let stable = match state.in_flight.first() {
+            Some(oldest) => oldest - 1,
+            None => state.next,
+        };
+        self.stable.store(stable, Ordering::Release);
+        self.advanced.notify_all();
+
+        let pending = state.unpublished.split_off(&(stable + 1));
+        let ready = std::mem::replace(&mut state.unpublished, pending);
+        state.ready.extend(ready);
+        if state.publishing || state.ready.is_empty() {
+            return false;
+        }
+        state.publishing = true;
+        true
+    }
+
+    /// Writes the publisher is to hand out next, in commit order. Once there
+    /// are none it stops being the publisher.
+    fn take_ready(&self) -> Vec<(CommitSeq, Vec<StoredObject>)> {
+        let mut state = self.state.lock().unwrap();
+        let ready = std::mem::take(&mut state.ready);
+        if ready.is_empty() {
+            state.publishing = false;
+        }
+        ready
+    }
+
+    fn stable(&self) -> CommitSeq {
+        self.stable.load(Ordering::Acquire)
+    }
+
+    fn wait_stable(&self, seq: CommitSeq) {
+        let mut state = self.state.lock().unwrap();
+        while self.stable() < seq {
+            state = self.advanced.wait(state).unwrap();
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct SVMMemory {
-    objects: Arc<DashMap<Vec<u8>, SVMObject<SVMPrimitives>>>,
+    objects: Arc<DashMap<Vec<u8>, ObjectVersions>>,
+    /// key -> id of the transaction owning the lock
+    locks: Arc<DashMap<Vec<u8>, u64>>,
+    /// where `lock_keys` waits for a busy key to be unlocked
+    lock_waits: Arc<LockWaits>,
+    next_owner: Arc<AtomicU64>,
+    clock: Arc<CommitClock>,
+    /// sequence -> number of open snapshots reading at it
+    pins: Arc<Mutex<BTreeMap<CommitSeq, usize>>>,
+    /// versions only visible below this sequence have been collected
+    gc_horizon: Arc<AtomicU64>,
+    retention: u64,
+    /// every key ever written, in byte order, for prefix and range scans
+    index: Arc<RwLock<BTreeSet<Vec<u8>>>>,
+    /// held shared by commits creating keys and exclusively by commits that
+    /// validate range reads, so no key can appear inside a validated range
+    range_guard: Arc<RwLock<()>>,
+    contention: Arc<ContentionStats>,
+    /// where commits are persisted, none keeps them in memory only
+    storage: Option<Arc<dyn Storage>>,
+    wal: Option<Arc<Wal>>,
+    /// merkle tree over the latest objects, rehashed when the root is read
+    state: Arc<Mutex<StateTree>>,
+    subscriptions: Arc<Subscriptions>,
+}
+
+impl Default for SVMMemory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SVMMemory {
     pub fn new() -> Self {
         Self {
             objects: Arc::new(DashMap::new()),
+            locks: Arc::new(DashMap::new()),
+            lock_waits: Arc::new(LockWaits::default()),
+            next_owner: Arc::new(AtomicU64::new(1)),
+            clock: Arc::new(CommitClock::default()),
+            pins: Arc::new(Mutex::new(BTreeMap::new())),
+            gc_horizon: Arc::new(AtomicU64::new(0)),
+            retention: DEFAULT_HISTORY_RETENTION,
+            index: Arc::new(RwLock::new(BTreeSet::new())),
+            range_guard: Arc::new(RwLock::new(())),
+            contention: Arc::new(ContentionStats::default()),
+            storage: None,
+            wal: None,
+            state: Arc::new(Mutex::new(StateTree::default())),
+            subscriptions: Arc::new(Subscriptions::default()),
+        }
+    }
+
+    /// Logs every commit to `wal` before applying it. Call
+    /// [`SVMMemory::replay`] with the records found when opening it.
+    pub fn with_wal(mut self, wal: Arc<Wal>) -> Self {
+        self.wal = Some(wal);
+        self
+    }
+
+    /// Re-applies logged commits on top of what [`SVMMemory::load`] brought
+    /// back. Writes the storage already has are skipped, so replaying is
+    /// idempotent. Returns how many commits had writes missing.
+    pub fn replay(&self, records: Vec<WalRecord>) -> std::io::Result<usize> {
+        let mut replayed = 0;
+        let mut last_seq = 0;
+        for record in records {
+            last_seq = last_seq.max(record.seq);
+            let missing: Vec<StoredObject> = record
+                .writes
+                .into_iter()
+                .filter(|(key, object)| {
+                    !matches!(self.get(key.clone()), Some(stored) if stored.version >= object.version)
+                })
+                .collect();
+            if missing.is_empty() {
+                continue;
+            }
+
+            self.persist(&missing)?;
+            let seq = self.clock.begin();
+            self.apply(seq, missing);
+            self.end_commit(seq);
+            replayed += 1;
+        }
+        self.clock.advance_past(last_seq);
+        Ok(replayed)
+    }
+
+    pub fn wal(&self) -> Option<&Arc<Wal>> {
+        self.wal.as_ref()
+    }
+
+    /// Replaces what the storage holds with `objects`, the full state at a
+    /// checkpoint, so it stops growing with history.
+    pub fn compact_storage(&self, objects: &[StoredObject]) -> std::io::Result<()> {
+        match &self.storage {
+            Some(storage) => storage.compact(objects),
+            None => Ok(()),
+        }
+    }
+
+    /// Persists every commit to `storage`. Call [`SVMMemory::load`] to bring
+    /// back what it already holds.
+    pub fn with_storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Numbers commits from now on after `seq`, e.g. the last one of a
+    /// state restored from disk.
+    pub fn advance_past(&self, seq: CommitSeq) {
+        self.clock.advance_past(seq);
+    }
+
+    /// Fills the memory with every object in the storage, keeping their
+    /// versions. Returns how many objects were loaded.
+    pub fn load(&self) -> std::io::Result<usize> {
+        let objects = match &self.storage {
+            Some(storage) => storage.load()?,
+            None => vec![],
+        };
+        let seq = self.clock.begin();
+        self.apply(seq, objects.iter().cloned());
+        self.end_commit(seq);
+        Ok(objects.len())
+    }
+
+    /// Writes `objects` with their versions as they are, e.g. to seed a
+    /// fresh node from a state snapshot. `seq` is the commit sequence the
+    /// objects were taken at, later commits are numbered after it.
+    pub fn import(&self, seq: CommitSeq, objects: &[StoredObject]) -> std::io::Result<()> {
+        self.clock.advance_past(seq);
+        let seq = self.clock.begin();
+        let written = self
+            .log_commit(seq, None, objects)
+            .and_then(|()| self.persist(objects));
+        if written.is_ok() {
+            self.apply(seq, objects.iter().cloned());
+        }
+        self.end_commit(seq);
+        written
+    }
+
+    /// Makes `writes` the latest versions of their keys as of `seq`.
+    /// Subscribers get them once `seq` ends, see [`SVMMemory::end_commit`].
+    fn apply<I>(&self, seq: CommitSeq, writes: I)
+    where
+        I: IntoIterator<Item = StoredObject>,
+    {
+        let writes: Vec<StoredObject> = writes.into_iter().collect();
+        let leaves: Vec<(Vec<u8>, Hash)> = writes
+            .iter()
+            .map(|(key, object)| (key.clone(), leaf_hash(key, object.version, &object.value)))
+            .collect();
+        self.state.lock().unwrap().set_leaves(seq, leaves);
+        if !self.subscriptions.is_empty() {
+            self.clock.stage(seq, writes.clone());
+        }
+        for (key, object) in writes {
+            self.index_key(&key);
+            self.objects.entry(key).or_default().push(seq, object);
+        }
+    }
+
+    /// Ends commit `seq` and publishes, in commit order, the writes of every
+    /// commit that is now stable, unless another committer is publishing and
+    /// takes them along. Neither holds the clock while publishing.
+    fn end_commit(&self, seq: CommitSeq) {
+        if !self.clock.end(seq) {
+            return;
+        }
+        loop {
+            let ready = self.clock.take_ready();
+            if ready.is_empty() {
+                return;
+            }
+            for (seq, writes) in ready {
+                self.subscriptions.publish(seq, &writes);
+            }
         }
     }
 
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    fn log_commit(
+        &self,
+        seq: CommitSeq,
+        tx: Option<&TxBody>,
+        writes: &[StoredObject],
+    ) -> std::io::Result<()> {
+        match &self.wal {
+            Some(wal) => wal.append(&WalRecord {
+                seq,
+                tx: tx.cloned(),
+                writes: writes.to_vec(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn persist(&self, objects: &[StoredObject]) -> std::io::Result<()> {
+        match &self.storage {
+            Some(storage) => storage.write_batch(objects),
+            None => Ok(()),
+        }
+    }
+
+    /// Subscribers told about the writes of every commit.
+    pub fn subscriptions(&self) -> &Subscriptions {
+        &self.subscriptions
+    }
+
+    /// Abort counters of every key that made a commit fail.
+    pub fn contention(&self) -> &ContentionStats {
+        &self.contention
+    }
+
+    fn record_abort(&self, reason: &AbortReason) {
+        debug!("transaction aborted: {}", reason);
+        self.contention.record(reason);
+    }
+
+    /// Number of commits of history kept for snapshot reads.
+    pub fn with_retention(mut self, retention: u64) -> Self {
+        self.retention = retention;
+        self
+    }
+
     pub fn get(&self, key: Vec<u8>) -> Option<SVMObject<SVMPrimitives>> {
-        self.objects.get(&key).map(|x| x.value().clone())
+        self.objects.get(&key).and_then(|x| x.latest().cloned())
+    }
+
+    /// Writes `object` as it is, outside of any transaction. Nothing is
+    /// applied if it could not be logged or persisted.
+    pub fn set(&self, key: Vec<u8>, object: SVMObject<SVMPrimitives>) -> std::io::Result<()> {
+        let seq = self.clock.begin();
+        let writes = vec![(key, object)];
+        let written = self
+            .log_commit(seq, None, &writes)
+            .and_then(|()| self.persist(&writes));
+        if written.is_ok() {
+            self.apply(seq, writes);
+        }
+        self.end_commit(seq);
+        written
+    }
+
+    fn index_key(&self, key: &[u8]) {
+        if !self.objects.contains_key(key) {
+            self.index.write().unwrap().insert(key.to_vec());
+        }
+    }
+
+    /// Up to `limit` keys in `range` that exist in the latest state, in byte order.
+    pub fn keys(&self, range: &KeyRange, limit: usize) -> Vec<Vec<u8>> {
+        if !range.is_valid() {
+            return vec![];
+        }
+        let index = self.index.read().unwrap();
+        index
+            .range::<[u8], _>(range.bounds())
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Merkle root over the latest version of every object, as of the last
+    /// applied commit. Equal states have equal roots.
+    pub fn state_root(&self) -> Hash {
+        self.state.lock().unwrap().root()
+    }
+
+    /// Latest object at `key` with the state root and a proof tying the two
+    /// together, all taken at the same moment.
+    pub fn get_with_proof(
+        &self,
+        key: &[u8],
+    ) -> (Option<SVMObject<SVMPrimitives>>, Hash, StateProof) {
+        // commits change the objects outside of the tree's lock, so read
+        // again until both hold the same version
+        loop {
+            let object = self.get(key.to_vec());
+            let mut state = self.state.lock().unwrap();
+            let leaf = object
+                .as_ref()
+                .map(|object| leaf_hash(key, object.version, &object.value));
+            if state.leaf(key) == leaf {
+                return (object, state.root(), state.prove(key));
+            }
+        }
+    }
+
+    /// Latest sequence whose writes, and all writes before it, are visible.
+    pub fn latest_seq(&self) -> CommitSeq {
+        self.clock.stable()
+    }
+
+    /// Blocks until the writes of commit `seq`, and all before it, are
+    /// visible to reads of the latest state.
+    pub fn wait_visible(&self, seq: CommitSeq) {
+        self.clock.wait_stable(seq)
+    }
+
+    pub fn gc_horizon(&self) -> CommitSeq {
+        self.gc_horizon.load(Ordering::Acquire)
+    }
+
+    /// Consistent read-only view of the latest committed state.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut pins = self.pins.lock().unwrap();
+        let seq = self.latest_seq();
+        *pins.entry(seq).or_default() += 1;
+        Snapshot {
+            tm: self.clone(),
+            seq,
+        }
+    }
+
+    /// Consistent read-only view of the state right after commit `seq`.
+    pub fn snapshot_at(&self, seq: CommitSeq) -> Result<Snapshot, String> {
+        let mut pins = self.pins.lock().unwrap();
+        let (horizon, latest) = (self.gc_horizon(), self.latest_seq());
+        if seq < horizon {
+            return Err(format!("seq={} is below the gc horizon={}", seq, horizon));
+        }
+        if seq > latest {
+            return Err(format!("seq={} is ahead of the latest seq={}", seq, latest));
+        }
+        *pins.entry(seq).or_default() += 1;
+        Ok(Snapshot {
+            tm: self.clone(),
+            seq,
+        })
+    }
+
+    fn unpin(&self, seq: CommitSeq) {
+        let mut pins = self.pins.lock().unwrap();
+        if let Some(count) = pins.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                pins.remove(&seq);
+            }
+        }
+    }
+
+    /// Drops object versions older than the retention window that no open
+    /// snapshot can still read. Returns the new GC horizon.
+    pub fn collect_garbage(&self) -> CommitSeq {
+        let horizon = {
+            let pins = self.pins.lock().unwrap();
+            let mut horizon = self.latest_seq().saturating_sub(self.retention);
+            if let Some(oldest_pin) = pins.keys().next() {
+                horizon = horizon.min(*oldest_pin);
+            }
+            horizon = horizon.max(self.gc_horizon());
+            self.gc_horizon.store(horizon, Ordering::Release);
+            horizon
+        };
+
+        for mut versions in self.objects.iter_mut() {
+            versions.prune(horizon);
+        }
+        horizon
+    }
+
+    fn new_owner(&self) -> u64 {
+        self.next_owner.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn try_lock(&self, key: &[u8], owner: u64) -> LockState {
+        match self.locks.entry(key.to_vec()) {
+            Entry::Vacant(entry) => {
+                entry.insert(owner);
+                LockState::Acquired
+            }
+            Entry::Occupied(entry) if *entry.get() == owner => LockState::AlreadyHeld,
+            Entry::Occupied(_) => LockState::Busy,
+        }
+    }
+
+    fn unlock(&self, key: &[u8], owner: u64) {
+        if self
+            .locks
+            .remove_if(key, |_, holder| *holder == owner)
+            .is_some()
+        {
+            let (waiting, unlocked) = self.lock_waits.stripe(key);
+            let _waiting = waiting.lock().unwrap();
+            unlocked.notify_all();
+        }
+    }
+
+    /// Blocks until every key is locked by a fresh owner. Keys are taken in
+    /// sorted order so two lockers can never wait on each other.
+    pub fn lock_keys(&self, keys: &[Vec<u8>]) -> KeyLocks<'_> {
+        let mut keys = keys.to_vec();
+        keys.sort();
+        keys.dedup();
+
+        let owner = self.new_owner();
+        for key in &keys {
+            // held while trying, so an unlock cannot slip in before the wait
+            let (waiting, unlocked) = self.lock_waits.stripe(key);
+            let mut waiting = waiting.lock().unwrap();
+            while let LockState::Busy = self.try_lock(key, owner) {
+                waiting = unlocked.wait(waiting).unwrap();
+            }
+        }
+
+        KeyLocks {
+            tm: self,
+            owner,
+            keys,
+        }
+    }
+}
+
+/// Read-only view of [`SVMMemory`] as of one commit sequence. Versions it can
+/// see are kept alive by the GC until it is dropped.
+pub struct Snapshot {
+    tm: SVMMemory,
+    seq: CommitSeq,
+}
+
+impl Snapshot {
+    pub fn seq(&self) -> CommitSeq {
+        self.seq
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<SVMObject<SVMPrimitives>> {
+        self.tm
+            .objects
+            .get(key)
+            .and_then(|versions| versions.at(self.seq).cloned())
+    }
+
+    /// Up to `limit` objects in `range` as of this snapshot, in key order.
+    pub fn scan(&self, range: &KeyRange, limit: usize) -> Vec<(Vec<u8>, SVMObject<SVMPrimitives>)> {
+        if !range.is_valid() {
+            return vec![];
+        }
+        // keys created after this snapshot are still in the index, skip them
+        let index = self.tm.index.read().unwrap();
+        index
+            .range::<[u8], _>(range.bounds())
+            .filter_map(|key| self.get(key).map(|object| (key.clone(), object)))
+            .take(limit)
+            .collect()
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.tm.unpin(self.seq);
+    }
+}
+
+/// Transaction that only reads. Every read goes to the snapshot taken when it
+/// started, so it never validates, never conflicts and never retries, and
+/// reads of several keys always observe the same moment.
+pub struct ReadOnlyTransaction {
+    snapshot: Snapshot,
+}
+
+impl ReadOnlyTransaction {
+    pub fn new(tm: &SVMMemory) -> Self {
+        Self {
+            snapshot: tm.snapshot(),
+        }
+    }
+
+    pub fn at(tm: &SVMMemory, seq: CommitSeq) -> Result<Self, String> {
+        Ok(Self {
+            snapshot: tm.snapshot_at(seq)?,
+        })
+    }
+
+    pub fn seq(&self) -> CommitSeq {
+        self.snapshot.seq()
+    }
+
+    pub fn read(&self, key: Vec<u8>) -> Option<SVMPrimitives> {
+        self.snapshot.get(&key).map(|object| object.value)
     }
 
-    pub fn set(&self, key: Vec<u8>, object: SVMObject<SVMPrimitives>) {
-        self.objects.insert(key, object);
+    pub fn read_many(&self, keys: &[Vec<u8>]) -> Vec<Option<SVMPrimitives>> {
+        keys.iter().map(|key| self.read(key.clone())).collect()
     }
+
+    pub fn scan(&self, range: &KeyRange, limit: usize) -> Vec<(Vec<u8>, SVMObject<SVMPrimitives>)> {
+        self.snapshot.scan(range, limit)
+    }
+}
+
+/// Stripes of keys that `lock_keys` callers wait on, woken when a key of
+/// their stripe is unlocked.
+struct LockWaits {
+    stripes: Vec<(Mutex<()>, Condvar)>,
+}
+
+impl Default for LockWaits {
+    fn default() -> Self {
+        Self {
+            stripes: (0..LOCK_WAIT_STRIPES).map(|_| Default::default()).collect(),
+        }
+    }
+}
+
+impl LockWaits {
+    fn stripe(&self, key: &[u8]) -> &(Mutex<()>, Condvar) {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.stripes[hasher.finish() as usize % self.stripes.len()]
+    }
+}
+
+/// Locks taken by [`SVMMemory::lock_keys`], released on drop.
+pub struct KeyLocks<'a> {
+    tm: &'a SVMMemory,
+    owner: u64,
+    keys: Vec<Vec<u8>>,
+}
+
+impl Drop for KeyLocks<'_> {
+    fn drop(&mut self) {
+        for key in &self.keys {
+            self.tm.unlock(key, self.owner);
+        }
+    }
+}
+
+/// A range scanned by a transaction and the stored keys it saw there.
+struct RangeRead {
+    range: KeyRange,
+    keys: Vec<Vec<u8>>,
 }
 
 pub struct Transaction<'a> {
     tm: &'a SVMMemory,
+    owner: u64,
     read_set: HashMap<Vec<u8>, (SVMPrimitives, Version)>,
     write_set: HashMap<Vec<u8>, SVMPrimitives>,
+    range_reads: Vec<RangeRead>,
+    /// the submitted transaction this runs, recorded in the write-ahead log
+    tx: Option<TxBody>,
 }
 
 impl<'a> Transaction<'a> {
     pub fn new(tm: &'a Arc<SVMMemory>) -> Self {
+        Self::with_owner(tm, tm.new_owner())
+    }
+
+    /// A transaction running on behalf of the holder of `owner` locks.
+    fn with_owner(tm: &'a SVMMemory, owner: u64) -> Self {
         Transaction {
             tm,
+            owner,
             read_set: HashMap::new(),
             write_set: HashMap::new(),
+            range_reads: vec![],
+            tx: None,
         }
     }
 
+    /// Records `tx_body` as the origin of this transaction's writes.
+    pub fn attach_tx(&mut self, tx_body: &TxBody) {
+        self.tx = Some(tx_body.clone());
+    }
+
     pub fn read(&mut self, key: Vec<u8>) -> Option<SVMPrimitives> {
         if let Some(value) = self.write_set.get(&key) {
             return Some(value.clone());
@@ -59,53 +848,305 @@ impl<'a> Transaction<'a> {
         self.write_set.insert(key, value);
     }
 
-    fn commit(&self) -> Result<(), &'static str> {
+    /// Keys read and written so far, in byte order.
+    pub fn keys(&self) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+        let mut reads: Vec<Vec<u8>> = self.read_set.keys().cloned().collect();
+        let mut writes: Vec<Vec<u8>> = self.write_set.keys().cloned().collect();
+        reads.sort();
+        writes.sort();
+        (reads, writes)
+    }
+
+    /// Hash of the values written so far, see [`writes_hash`].
+    pub fn write_hash(&self) -> Hash {
+        let mut writes: Vec<(&Vec<u8>, &SVMPrimitives)> = self.write_set.iter().collect();
+        writes.sort_by(|a, b| a.0.cmp(b.0));
+        writes_hash(writes)
+    }
+
+    /// Up to `limit` objects in `range`, including this transaction's own writes.
+    /// The commit aborts if another transaction changed or created a key in the
+    /// part of the range that was returned.
+    pub fn scan(&mut self, range: &KeyRange, limit: usize) -> Vec<(Vec<u8>, SVMPrimitives)> {
+        let stored = self.tm.keys(range, limit);
+        // when the limit cuts the scan short, only the returned part was observed
+        let observed = match stored.last() {
+            Some(last) if stored.len() == limit => range.up_to(last),
+            _ => range.clone(),
+        };
+
+        let mut entries = BTreeMap::new();
+        for key in &stored {
+            if let Some(value) = self.read(key.clone()) {
+                entries.insert(key.clone(), value);
+            }
+        }
+        for (key, value) in &self.write_set {
+            if observed.contains(key) {
+                entries.insert(key.clone(), value.clone());
+            }
+        }
+
+        self.range_reads.push(RangeRead {
+            range: observed,
+            keys: stored,
+        });
+        entries.into_iter().take(limit).collect()
+    }
+
+    /// Returns the sequence the writes were applied at.
+    fn commit(&self) -> Result<CommitSeq, AbortReason> {
+        // Lock everything we touched so validation and writes are not interleaved
+        // with another commit. Never wait here: a busy key aborts the transaction.
+        let mut keys: Vec<&Vec<u8>> = self.read_set.keys().chain(self.write_set.keys()).collect();
+        keys.sort();
+        keys.dedup();
+
+        let mut acquired = vec![];
+        let mut busy = None;
+        for key in keys {
+            match self.tm.try_lock(key, self.owner) {
+                LockState::Acquired => acquired.push(key),
+                LockState::AlreadyHeld => {}
+                LockState::Busy => {
+                    busy = Some(key);
+                    break;
+                }
+            }
+        }
+
+        let result = match busy {
+            Some(key) => Err(AbortReason::LockBusy { key: key.clone() }),
+            None => self.validate_and_write(),
+        };
+
+        for key in acquired {
+            self.tm.unlock(key, self.owner);
+        }
+        result
+    }
+
+    fn validate_and_write(&self) -> Result<CommitSeq, AbortReason> {
+        let creates_keys = self
+            .write_set
+            .keys()
+            .any(|key| !self.tm.objects.contains_key(key));
+        let _exclusive =
+            (!self.range_reads.is_empty()).then(|| self.tm.range_guard.write().unwrap());
+        let _shared =
+            (_exclusive.is_none() && creates_keys).then(|| self.tm.range_guard.read().unwrap());
+
         // Validate
         for (key, (_, version)) in &self.read_set {
-            if let Some(tv) = self.tm.objects.get(key) {
+            if let Some(tv) = self.tm.get(key.clone()) {
                 if tv.version != *version {
-                    return Err("Conflict detected, transaction aborted");
+                    return Err(AbortReason::VersionConflict {
+                        key: key.clone(),
+                        expected: *version,
+                        actual: tv.version,
+                    });
                 }
             }
         }
+        for range_read in &self.range_reads {
+            // keys are never removed, so any difference is a key created since the scan
+            let created = self
+                .tm
+                .keys(&range_read.range, usize::MAX)
+                .into_iter()
+                .find(|key| range_read.keys.binary_search(key).is_err());
+            if let Some(key) = created {
+                return Err(AbortReason::Phantom { key });
+            }
+        }
 
-        // Commit
-        for (key, value) in &self.write_set {
-            let version = self.tm.objects.get(key).map_or(0, |tv| tv.version) + 1;
-            self.tm.objects.insert(
-                key.clone(),
-                SVMObject {
+        // Commit: log the whole write set first so a crash halfway through
+        // applying it is repaired by replaying the log
+        let writes: Vec<StoredObject> = self
+            .write_set
+            .iter()
+            .map(|(key, value)| {
+                let version = self.tm.get(key.clone()).map_or(0, |tv| tv.version) + 1;
+                let object = SVMObject {
                     value: value.clone(),
                     version,
-                },
-            );
+                };
+                (key.clone(), object)
+            })
+            .collect();
+
+        let seq = self.tm.clock.begin();
+        let written = self
+            .tm
+            .log_commit(seq, self.tx.as_ref(), &writes)
+            .and_then(|()| self.tm.persist(&writes));
+        if let Err(e) = written {
+            self.tm.end_commit(seq);
+            return Err(AbortReason::Durability {
+                error: e.to_string(),
+            });
         }
+        self.tm.apply(seq, writes);
+        self.tm.end_commit(seq);
 
-        Ok(())
+        Ok(seq)
     }
 
     fn rollback(&mut self) {
         self.read_set.clear();
         self.write_set.clear();
+        self.range_reads.clear();
     }
 }
 
+/// Outcome of a transaction that committed.
+#[derive(Clone, Debug)]
+pub struct Committed {
+    pub ret_value: SVMPrimitives,
+    /// sequence its writes were applied at, i.e. its place in the serial order
+    pub seq: CommitSeq,
+    /// aborted attempts before the one that committed
+    pub retries: u32,
+    /// keys the committed attempt read and wrote, in byte order
+    pub reads: Vec<Vec<u8>>,
+    pub writes: Vec<Vec<u8>>,
+    /// hash of the values it wrote
+    pub write_hash: Hash,
+}
+
 pub fn retry_transaction<F>(tm: Arc<SVMMemory>, transaction_fn: F) -> Result<SVMPrimitives, String>
 where
     F: Fn(&mut Transaction) -> Result<SVMPrimitives, String>,
 {
+    run_optimistic(&tm, transaction_fn).map(|committed| committed.ret_value)
+}
+
+/// Pessimistic variant of [`retry_transaction`]: `keys` are locked before the
+/// first execution and held until commit, so `transaction_fn` only re-runs if it
+/// touched keys it did not declare.
+pub fn locking_transaction<F>(
+    tm: Arc<SVMMemory>,
+    keys: &[Vec<u8>],
+    transaction_fn: F,
+) -> Result<SVMPrimitives, String>
+where
+    F: Fn(&mut Transaction) -> Result<SVMPrimitives, String>,
+{
+    run_locked(&tm, keys, transaction_fn).map(|committed| committed.ret_value)
+}
+
+pub fn execute_transaction<F>(
+    tm: Arc<SVMMemory>,
+    mode: ConcurrencyMode,
+    keys: &[Vec<u8>],
+    transaction_fn: F,
+) -> Result<Committed, String>
+where
+    F: Fn(&mut Transaction) -> Result<SVMPrimitives, String>,
+{
+    match mode {
+        ConcurrencyMode::Optimistic => run_optimistic(&tm, transaction_fn),
+        ConcurrencyMode::Locking => run_locked(&tm, keys, transaction_fn),
+    }
+}
+
+/// Why [`run_until_committed`] gave up.
+enum Stopped {
+    Failed(String),
+    /// another transaction holds a key the locked transaction did not declare
+    Undeclared(Vec<u8>),
+}
+
+fn run_optimistic<F>(tm: &SVMMemory, transaction_fn: F) -> Result<Committed, String>
+where
+    F: Fn(&mut Transaction) -> Result<SVMPrimitives, String>,
+{
+    let mut retries = 0;
+    match run_until_committed(tm, tm.new_owner(), &[], &mut retries, &transaction_fn) {
+        Ok(committed) => Ok(committed),
+        Err(Stopped::Failed(e)) => Err(e),
+        Err(Stopped::Undeclared(_)) => unreachable!("optimistic transactions hold no locks"),
+    }
+}
+
+/// Runs `transaction_fn` holding the locks of `keys`. Waiting for a key it
+/// did not declare while holding them could wait forever on a transaction
+/// that waits for one of them, so it gives them all up instead, backs off
+/// and locks again with that key declared too.
+fn run_locked<F>(tm: &SVMMemory, keys: &[Vec<u8>], transaction_fn: F) -> Result<Committed, String>
+where
+    F: Fn(&mut Transaction) -> Result<SVMPrimitives, String>,
+{
+    let mut keys = keys.to_vec();
+    let mut retries = 0;
     loop {
-        let mut txn = Transaction::new(&tm);
+        let locks = tm.lock_keys(&keys);
+        match run_until_committed(tm, locks.owner, &locks.keys, &mut retries, &transaction_fn) {
+            Ok(committed) => return Ok(committed),
+            Err(Stopped::Failed(e)) => return Err(e),
+            Err(Stopped::Undeclared(key)) => {
+                drop(locks);
+                debug!(
+                    "released locks to wait for undeclared key={}",
+                    String::from_utf8_lossy(&key)
+                );
+                keys.push(key);
+                sleep(Duration::from_micros(10 << retries.min(10)));
+            }
+        }
+    }
+}
+
+/// Runs and commits `transaction_fn` as `owner`, which holds the locks of
+/// the sorted `locked` keys, retrying while it conflicts.
+fn run_until_committed<F>(
+    tm: &SVMMemory,
+    owner: u64,
+    locked: &[Vec<u8>],
+    retries: &mut u32,
+    transaction_fn: &F,
+) -> Result<Committed, Stopped>
+where
+    F: Fn(&mut Transaction) -> Result<SVMPrimitives, String>,
+{
+    loop {
+        let mut txn = Transaction::with_owner(tm, owner);
         let ret_val = match transaction_fn(&mut txn) {
             Ok(ret_val) => ret_val,
             Err(e) => {
-                return Err(format!("transaction_fn execution failed err={}", e));
+                return Err(Stopped::Failed(format!(
+                    "transaction_fn execution failed err={}",
+                    e
+                )));
             }
         };
 
         match txn.commit() {
-            Ok(_) => return Ok(ret_val),
-            Err(_) => {
+            Ok(seq) => {
+                let (reads, writes) = txn.keys();
+                return Ok(Committed {
+                    ret_value: ret_val,
+                    seq,
+                    retries: *retries,
+                    reads,
+                    writes,
+                    write_hash: txn.write_hash(),
+                });
+            }
+            Err(reason) if !reason.is_retryable() => {
+                return Err(Stopped::Failed(format!("commit failed err={}", reason)));
+            }
+            Err(reason) => {
+                tm.record_abort(&reason);
+                *retries += 1;
+                match reason {
+                    AbortReason::LockBusy { key }
+                        if !locked.is_empty() && locked.binary_search(&key).is_err() =>
+                    {
+                        return Err(Stopped::Undeclared(key));
+                    }
+                    _ => {}
+                }
                 txn.rollback();
                 sleep(Duration::from_micros(10)); // Simple backoff strategy
             }
@@ -140,7 +1181,14 @@ where
         let now = Instant::now();
         match txn.commit() {
             Ok(_) => return (Ok(ret_val), (vm_mrs, mem_mrs, backoff_mrs)),
-            Err(_) => {
+            Err(reason) if !reason.is_retryable() => {
+                return (
+                    Err(format!("commit failed err={}", reason)),
+                    (vm_mrs, mem_mrs, backoff_mrs),
+                );
+            }
+            Err(reason) => {
+                smem.record_abort(&reason);
                 txn.rollback();
                 sleep(Duration::from_micros(10)); // Simple backoff strategy
             }
@@ -148,3 +1196,431 @@ where
         backoff_mrs += now.elapsed().as_micros();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_stm::subscriptions::Filter;
+    use crate::storage::MemoryStorage;
+    use std::sync::atomic::AtomicUsize;
+
+    const MODES: [ConcurrencyMode; 2] = [ConcurrencyMode::Optimistic, ConcurrencyMode::Locking];
+
+    fn key(i: u32) -> Vec<u8> {
+        format!("0x{}", i).into_bytes()
+    }
+
+    fn balance(tm: &SVMMemory, i: u32) -> u32 {
+        match tm.get(key(i)).map(|obj| obj.value) {
+            Some(SVMPrimitives::U24(v)) => v,
+            other => panic!("unexpected balance {:?}", other),
+        }
+    }
+
+    fn alloc(tm: &Arc<SVMMemory>, n: u32, amount: u32) {
+        for i in 0..n {
+            retry_transaction(tm.clone(), |txn| {
+                txn.write(key(i), SVMPrimitives::U24(amount));
+                Ok(SVMPrimitives::Era)
+            })
+            .unwrap();
+        }
+    }
+
+    fn transfer(tm: &Arc<SVMMemory>, mode: ConcurrencyMode, from: u32, to: u32, amt: u32) {
+        execute_transaction(tm.clone(), mode, &[key(from), key(to)], |txn| {
+            let (Some(SVMPrimitives::U24(a)), Some(SVMPrimitives::U24(b))) =
+                (txn.read(key(from)), txn.read(key(to)))
+            else {
+                return Err("missing account".to_string());
+            };
+            txn.write(key(from), SVMPrimitives::U24(a - amt));
+            txn.write(key(to), SVMPrimitives::U24(b + amt));
+            Ok(SVMPrimitives::Era)
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn concurrent_transfers_preserve_balances() {
+        for mode in MODES {
+            let tm = Arc::new(SVMMemory::new());
+            alloc(&tm, 10, 1_000);
+
+            // every account sends 1 to every other account, 3 times
+            std::thread::scope(|s| {
+                for from in 0..10 {
+                    let tm = tm.clone();
+                    s.spawn(move || {
+                        for _ in 0..3 {
+                            for to in (0..10).filter(|to| *to != from) {
+                                transfer(&tm, mode, from, to, 1);
+                            }
+                        }
+                    });
+                }
+            });
+
+            for i in 0..10 {
+                assert_eq!(balance(&tm, i), 1_000, "mode={:?} key={}", mode, i);
+            }
+        }
+    }
+
+    #[test]
+    fn lockers_wait_until_the_key_is_unlocked() {
+        let tm = SVMMemory::new();
+        let held = tm.lock_keys(&[key(0)]);
+        std::thread::scope(|s| {
+            let waiter = s.spawn(|| drop(tm.lock_keys(&[key(0), key(1)])));
+            sleep(Duration::from_millis(50));
+            assert!(!waiter.is_finished());
+            drop(held);
+        });
+        assert!(tm.locks.is_empty());
+    }
+
+    #[test]
+    fn locked_transactions_touching_each_others_keys_commit() {
+        let tm = Arc::new(SVMMemory::new());
+        alloc(&tm, 2, 1_000);
+        // each locks one account and moves money to the other, undeclared
+        std::thread::scope(|s| {
+            for (from, to) in [(0, 1), (1, 0)] {
+                let tm = tm.clone();
+                s.spawn(move || {
+                    for _ in 0..200 {
+                        locking_transaction(tm.clone(), &[key(from)], |txn| {
+                            let (Some(SVMPrimitives::U24(a)), Some(SVMPrimitives::U24(b))) =
+                                (txn.read(key(from)), txn.read(key(to)))
+                            else {
+                                return Err("missing account".to_string());
+                            };
+                            txn.write(key(from), SVMPrimitives::U24(a - 1));
+                            txn.write(key(to), SVMPrimitives::U24(b + 1));
+                            Ok(SVMPrimitives::Era)
+                        })
+                        .unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!((balance(&tm, 0), balance(&tm, 1)), (1_000, 1_000));
+        assert!(tm.locks.is_empty());
+    }
+
+    #[test]
+    fn hot_key_counter() {
+        for mode in MODES {
+            let tm = Arc::new(SVMMemory::new());
+            alloc(&tm, 1, 0);
+            let runs = Arc::new(AtomicUsize::new(0));
+
+            std::thread::scope(|s| {
+                for _ in 0..8 {
+                    let tm = tm.clone();
+                    let runs = runs.clone();
+                    s.spawn(move || {
+                        for _ in 0..50 {
+                            execute_transaction(tm.clone(), mode, &[key(0)], |txn| {
+                                runs.fetch_add(1, Ordering::Relaxed);
+                                let Some(SVMPrimitives::U24(v)) = txn.read(key(0)) else {
+                                    return Err("missing counter".to_string());
+                                };
+                                txn.write(key(0), SVMPrimitives::U24(v + 1));
+                                Ok(SVMPrimitives::Era)
+                            })
+                            .unwrap();
+                        }
+                    });
+                }
+            });
+
+            assert_eq!(balance(&tm, 0), 400, "mode={:?}", mode);
+            assert_eq!(tm.get(key(0)).unwrap().version, 401, "mode={:?}", mode);
+            if mode == ConcurrencyMode::Locking {
+                // declared keys are locked up front, nothing is ever re-executed
+                assert_eq!(runs.load(Ordering::Relaxed), 400);
+            }
+        }
+    }
+
+    fn write(tm: &Arc<SVMMemory>, i: u32, amount: u32) {
+        retry_transaction(tm.clone(), |txn| {
+            txn.write(key(i), SVMPrimitives::U24(amount));
+            Ok(SVMPrimitives::Era)
+        })
+        .unwrap();
+    }
+
+    fn value_at(snapshot: &Snapshot, i: u32) -> Option<u32> {
+        match snapshot.get(&key(i)).map(|obj| obj.value) {
+            Some(SVMPrimitives::U24(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn snapshot_reads_are_stable() {
+        let tm = Arc::new(SVMMemory::new());
+        write(&tm, 0, 1);
+        let first = tm.latest_seq();
+        let snapshot = tm.snapshot();
+        write(&tm, 0, 2);
+        write(&tm, 1, 3);
+
+        assert_eq!(snapshot.seq(), first);
+        assert_eq!(value_at(&snapshot, 0), Some(1));
+        assert_eq!(value_at(&snapshot, 1), None);
+        assert_eq!(balance(&tm, 0), 2);
+
+        let latest = tm.snapshot_at(tm.latest_seq()).unwrap();
+        assert_eq!(value_at(&latest, 0), Some(2));
+        assert_eq!(value_at(&latest, 1), Some(3));
+        assert!(tm.snapshot_at(tm.latest_seq() + 1).is_err());
+    }
+
+    #[test]
+    fn gc_keeps_pinned_versions() {
+        let tm = Arc::new(SVMMemory::new().with_retention(0));
+        for amount in 0..5 {
+            write(&tm, 0, amount);
+        }
+        let pinned = tm.snapshot_at(2).unwrap();
+        write(&tm, 0, 5);
+
+        assert_eq!(tm.collect_garbage(), 2);
+        assert_eq!(value_at(&pinned, 0), Some(1));
+        assert!(tm.snapshot_at(1).is_err());
+
+        drop(pinned);
+        assert_eq!(tm.collect_garbage(), tm.latest_seq());
+        assert_eq!(tm.objects.get(&key(0)).unwrap().0.len(), 1);
+        assert_eq!(balance(&tm, 0), 5);
+    }
+
+    #[test]
+    fn read_only_transaction_sees_one_moment() {
+        let tm = Arc::new(SVMMemory::new());
+        alloc(&tm, 2, 500);
+
+        std::thread::scope(|s| {
+            let writer = tm.clone();
+            s.spawn(move || {
+                for i in 0..200 {
+                    let (from, to) = if i % 2 == 0 { (0, 1) } else { (1, 0) };
+                    transfer(&writer, ConcurrencyMode::Optimistic, from, to, 7);
+                }
+            });
+
+            for _ in 0..200 {
+                let txn = ReadOnlyTransaction::new(&tm);
+                let values = txn.read_many(&[key(0), key(1)]);
+                match values.as_slice() {
+                    [Some(SVMPrimitives::U24(a)), Some(SVMPrimitives::U24(b))] => {
+                        assert_eq!(a + b, 1_000, "seq={}", txn.seq())
+                    }
+                    other => panic!("unexpected values {:?}", other),
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn prefix_scan_with_pagination() {
+        let tm = Arc::new(SVMMemory::new());
+        for i in [1, 10, 100, 101, 11, 2, 20] {
+            write(&tm, i, i);
+        }
+        let snapshot = tm.snapshot();
+        let range = KeyRange::prefix(b"0x10");
+
+        let first = snapshot.scan(&range, 1);
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].0, key(10));
+        let rest = snapshot.scan(&range.after(&first[0].0), 10);
+        let keys: Vec<_> = rest.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![key(100), key(101)]);
+
+        write(&tm, 102, 102);
+        assert_eq!(snapshot.scan(&range, 10).len(), 3);
+        assert_eq!(tm.snapshot().scan(&range, 10).len(), 4);
+    }
+
+    #[test]
+    fn scan_aborts_on_phantom() {
+        let tm = Arc::new(SVMMemory::new());
+        write(&tm, 10, 1);
+        let runs = AtomicUsize::new(0);
+
+        let count = retry_transaction(tm.clone(), |txn| {
+            let entries = txn.scan(&KeyRange::prefix(b"0x1"), 100);
+            if runs.fetch_add(1, Ordering::Relaxed) == 0 {
+                // another transaction creates a key inside the scanned range
+                write(&tm, 11, 1);
+            }
+            txn.write(key(0), SVMPrimitives::U24(entries.len() as u32));
+            Ok(SVMPrimitives::Era)
+        });
+
+        assert!(count.is_ok());
+        assert_eq!(runs.load(Ordering::Relaxed), 2);
+        assert_eq!(balance(&tm, 0), 2);
+    }
+
+    #[test]
+    fn aborts_are_recorded_per_key() {
+        let tm = Arc::new(SVMMemory::new());
+        alloc(&tm, 2, 0);
+
+        let mut txn = Transaction::new(&tm);
+        txn.read(key(0));
+        txn.read(key(1));
+        txn.write(key(1), SVMPrimitives::U24(1));
+        write(&tm, 0, 5);
+        let reason = txn.commit().unwrap_err();
+        assert_eq!(
+            reason,
+            AbortReason::VersionConflict {
+                key: key(0),
+                expected: 1,
+                actual: 2
+            }
+        );
+
+        tm.record_abort(&reason);
+        tm.record_abort(&AbortReason::LockBusy { key: key(1) });
+        tm.record_abort(&AbortReason::LockBusy { key: key(0) });
+        let top = tm.contention().top(1);
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].0, key(0));
+        assert_eq!(top[0].1.aborts, 2);
+        assert_eq!(top[0].1.last_actual_version, Some(2));
+    }
+
+    #[test]
+    fn commits_survive_reload_from_storage() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let tm = Arc::new(SVMMemory::new().with_storage(storage.clone()));
+        alloc(&tm, 3, 10);
+        transfer(&tm, ConcurrencyMode::Optimistic, 0, 2, 4);
+
+        let restarted = SVMMemory::new().with_storage(storage);
+        assert_eq!(restarted.load().unwrap(), 3);
+        assert_eq!(balance(&restarted, 0), 6);
+        assert_eq!(balance(&restarted, 2), 14);
+        assert_eq!(restarted.get(key(2)).unwrap().version, 2);
+        assert_eq!(restarted.keys(&KeyRange::all(), 10).len(), 3);
+        assert_eq!(restarted.state_root(), tm.state_root());
+    }
+
+    #[test]
+    fn failed_storage_write_fails_the_commit() {
+        struct FullDisk;
+        impl Storage for FullDisk {
+            fn load(&self) -> std::io::Result<Vec<StoredObject>> {
+                Ok(vec![])
+            }
+
+            fn write_batch(&self, _objects: &[StoredObject]) -> std::io::Result<()> {
+                Err(std::io::Error::other("disk full"))
+            }
+        }
+
+        let tm = Arc::new(SVMMemory::new().with_storage(Arc::new(FullDisk)));
+        let e = retry_transaction(tm.clone(), |txn| {
+            txn.write(key(0), SVMPrimitives::U24(1));
+            Ok(SVMPrimitives::Era)
+        })
+        .unwrap_err();
+        assert_eq!(e, "commit failed err=durability error=disk full");
+        assert!(tm.get(key(0)).is_none());
+    }
+
+    #[test]
+    fn parallel_and_sequential_runs_share_state_root() {
+        let transfers: Vec<(u32, u32)> = (0..10)
+            .flat_map(|from| {
+                (0..10)
+                    .filter(move |to| *to != from)
+                    .map(move |to| (from, to))
+            })
+            .collect();
+
+        let sequential = Arc::new(SVMMemory::new());
+        alloc(&sequential, 10, 1_000);
+        for (from, to) in &transfers {
+            transfer(&sequential, ConcurrencyMode::Optimistic, *from, *to, 1);
+        }
+
+        for mode in MODES {
+            let parallel = Arc::new(SVMMemory::new());
+            alloc(&parallel, 10, 1_000);
+            std::thread::scope(|s| {
+                for chunk in transfers.chunks(9) {
+                    let tm = parallel.clone();
+                    s.spawn(move || {
+                        for (from, to) in chunk.iter().rev() {
+                            transfer(&tm, mode, *from, *to, 1);
+                        }
+                    });
+                }
+            });
+            assert_eq!(
+                parallel.state_root(),
+                sequential.state_root(),
+                "mode={:?}",
+                mode
+            );
+        }
+        assert_ne!(sequential.state_root(), SVMMemory::new().state_root());
+    }
+
+    #[test]
+    fn changes_are_published_in_commit_order() {
+        let tm = SVMMemory::new();
+        let mut subscription = tm.subscriptions().subscribe(Filter {
+            prefixes: vec![b"0x".to_vec()],
+            ..Filter::default()
+        });
+        let object = |v| SVMObject {
+            value: SVMPrimitives::U24(v),
+            version: v as u64,
+        };
+
+        // the later commit applies and ends first
+        let (first, second) = (tm.clock.begin(), tm.clock.begin());
+        tm.apply(second, [(key(1), object(2))]);
+        tm.end_commit(second);
+        assert!(subscription.changes.try_recv().is_err());
+        assert_eq!(tm.get_with_proof(&key(1)).0.map(|obj| obj.version), Some(2));
+
+        tm.apply(first, [(key(1), object(1))]);
+        tm.end_commit(first);
+        assert_eq!(tm.get(key(1)).map(|obj| obj.version), Some(2));
+        assert_eq!(subscription.changes.try_recv().unwrap().seq, first);
+        assert_eq!(subscription.changes.try_recv().unwrap().seq, second);
+
+        let mut expected = StateTree::default();
+        expected.update([&(key(1), object(2))]);
+        assert_eq!(tm.state_root(), expected.root());
+    }
+
+    #[test]
+    fn commits_become_visible_once_earlier_ones_end() {
+        let tm = SVMMemory::new();
+        let (first, second) = (tm.clock.begin(), tm.clock.begin());
+        tm.end_commit(second);
+        let waiter = {
+            let tm = tm.clone();
+            std::thread::spawn(move || tm.wait_visible(second))
+        };
+        sleep(Duration::from_millis(50));
+        assert!(!waiter.is_finished());
+        assert_eq!(tm.latest_seq(), first - 1);
+
+        tm.end_commit(first);
+        waiter.join().unwrap();
+        assert_eq!(tm.latest_seq(), second);
+    }
+}
@@ -0,0 +1,71 @@
+use crate::merkle::Hash;
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Largest value decoded, so a corrupt length cannot exhaust memory.
+pub const MAX_DECODE_BYTES: u64 = 64 << 20;
+
+/// Variable-length integers and lengths, little-endian, no trailing bytes.
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+/// Canonical binary encoding of `value`. Equal values always encode to the
+/// same bytes, so they are what transactions, blocks and the state are
+/// hashed over, and what binary WebSocket frames carry.
+pub fn encode<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+    options().serialize(value).unwrap()
+}
+
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, String> {
+    options()
+        .with_limit(MAX_DECODE_BYTES)
+        .deserialize(bytes)
+        .map_err(|e| e.to_string())
+}
+
+/// Sha256 of the canonical encoding of `value`.
+pub fn hash<T: Serialize + ?Sized>(value: &T) -> Hash {
+    Sha256::digest(encode(value)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::types::{TxBody, TxResult};
+    use crate::svm::primitive_types::SVMPrimitives;
+    use crate::ws::events::{Message, SubmitTx};
+
+    #[test]
+    fn encoding_is_compact_and_round_trips() {
+        let tx_body = TxBody {
+            tx_hash: String::new(),
+            code_hash: "0xduangua".to_string(),
+            objs: vec!["0xgame/1".to_string()],
+            args: vec![SVMPrimitives::Tup(
+                (0..1000).map(SVMPrimitives::U24).collect(),
+            )],
+        };
+        let message = Message::SubmitTx(SubmitTx {
+            tx_body: tx_body.clone(),
+        });
+        let bytes = encode(&message);
+        assert!(bytes.len() * 3 < serde_json::to_vec(&message).unwrap().len());
+        let Message::SubmitTx(decoded) = decode::<Message>(&bytes).unwrap() else {
+            panic!("decoded another message");
+        };
+        assert_eq!(decoded.tx_body, tx_body);
+
+        let tx_result = TxResult::from_result(&tx_body, &Ok(SVMPrimitives::Era));
+        assert_eq!(decode::<TxResult>(&encode(&tx_result)).unwrap(), tx_result);
+        assert!(decode::<TxResult>(&bytes[..bytes.len() - 1]).is_err());
+
+        // the hash covers what runs, not the hash itself
+        let mut hashed = tx_body.clone();
+        hashed.tx_hash = tx_body.canonical_hash();
+        assert_eq!(hashed.canonical_hash(), tx_body.canonical_hash());
+        hashed.args.push(SVMPrimitives::U24(1));
+        assert_ne!(hashed.canonical_hash(), tx_body.canonical_hash());
+    }
+}
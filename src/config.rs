@@ -0,0 +1,195 @@
+use crate::block::builder::BlockConfig;
+use crate::block_stm::svm_memory::{ConcurrencyMode, DEFAULT_HISTORY_RETENTION};
+use crate::executor::engine::EngineConfig;
+use crate::storage::checkpoint::CheckpointConfig;
+use crate::ws::limits::LimitConfig;
+use log::error;
+use std::{env, path::PathBuf, time::Duration};
+
+pub struct NodeConfig {
+    pub ws_addr: String,
+    /// JSON-RPC over HTTP listen address, the server is off if unset
+    pub rpc_addr: Option<String>,
+    /// concurrency mode for every code not listed in `locking_codes`
+    pub concurrency_mode: ConcurrencyMode,
+    /// codes that always run with `ConcurrencyMode::Locking`, e.g. hot game state
+    pub locking_codes: Vec<String>,
+    /// commits of object history kept for snapshot reads
+    pub history_retention: u64,
+    /// directory of the durable file storage, state is kept in memory only if unset
+    pub data_dir: Option<PathBuf>,
+    /// where snapshot messages write and read, `snapshots` in `data_dir` if unset
+    pub snapshot_dir: Option<PathBuf>,
+    /// fsync every commit and write-ahead log record instead of only handing them to the OS
+    pub fsync: bool,
+    /// size and time window of produced blocks
+    pub blocks: BlockConfig,
+    /// threads and queue of the execution engine
+    pub engine: EngineConfig,
+    /// message size, rate and requests in flight allowed to clients
+    pub limits: LimitConfig,
+    /// file with the token admin messages need, they are refused if unset
+    pub admin_token_file: Option<PathBuf>,
+    /// genesis file with the initial objects, codes and chain parameters
+    pub genesis: Option<PathBuf>,
+    /// how often the state in `data_dir` is checkpointed and history pruned
+    pub checkpoints: CheckpointConfig,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            ws_addr: "0.0.0.0:9001".to_string(),
+            rpc_addr: Some("127.0.0.1:9002".to_string()),
+            concurrency_mode: ConcurrencyMode::Optimistic,
+            locking_codes: vec![],
+            history_retention: DEFAULT_HISTORY_RETENTION,
+            data_dir: None,
+            snapshot_dir: None,
+            fsync: false,
+            blocks: BlockConfig::default(),
+            engine: EngineConfig::default(),
+            limits: LimitConfig::default(),
+            admin_token_file: None,
+            genesis: None,
+            checkpoints: CheckpointConfig::default(),
+        }
+    }
+}
+
+impl NodeConfig {
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(addr) = env::var("SVM_WS_ADDR") {
+            config.ws_addr = addr;
+        }
+        if let Ok(addr) = env::var("SVM_RPC_ADDR") {
+            config.rpc_addr = Some(addr).filter(|addr| !addr.is_empty());
+        }
+        if let Ok(mode) = env::var("SVM_CONCURRENCY_MODE") {
+            match mode.parse() {
+                Ok(mode) => config.concurrency_mode = mode,
+                Err(e) => error!("ignoring SVM_CONCURRENCY_MODE err={}", e),
+            }
+        }
+        if let Ok(codes) = env::var("SVM_LOCKING_CODES") {
+            config.locking_codes = codes
+                .split(',')
+                .map(|code| code.trim().to_string())
+                .filter(|code| !code.is_empty())
+                .collect();
+        }
+        if let Ok(retention) = env::var("SVM_HISTORY_RETENTION") {
+            match retention.parse() {
+                Ok(retention) => config.history_retention = retention,
+                Err(e) => error!("ignoring SVM_HISTORY_RETENTION err={}", e),
+            }
+        }
+        if let Ok(dir) = env::var("SVM_DATA_DIR") {
+            config.data_dir = Some(PathBuf::from(dir));
+        }
+        if let Ok(dir) = env::var("SVM_SNAPSHOT_DIR") {
+            config.snapshot_dir = Some(PathBuf::from(dir));
+        }
+        if let Ok(fsync) = env::var("SVM_FSYNC") {
+            config.fsync = matches!(fsync.as_str(), "1" | "true");
+        }
+        if let Ok(path) = env::var("SVM_ADMIN_TOKEN_FILE") {
+            config.admin_token_file = Some(PathBuf::from(path));
+        }
+        if let Ok(genesis) = env::var("SVM_GENESIS") {
+            config.genesis = Some(PathBuf::from(genesis));
+        }
+        if let Ok(max_txs) = env::var("SVM_BLOCK_MAX_TXS") {
+            match max_txs.parse() {
+                Ok(max_txs) => config.blocks.max_txs = max_txs,
+                Err(e) => error!("ignoring SVM_BLOCK_MAX_TXS err={}", e),
+            }
+        }
+        if let Ok(interval) = env::var("SVM_BLOCK_INTERVAL_MS") {
+            match interval.parse() {
+                Ok(interval) => config.blocks.interval = Duration::from_millis(interval),
+                Err(e) => error!("ignoring SVM_BLOCK_INTERVAL_MS err={}", e),
+            }
+        }
+        if let Ok(workers) = env::var("SVM_EXEC_WORKERS") {
+            match workers.parse() {
+                Ok(workers) => config.engine.workers = workers,
+                Err(e) => error!("ignoring SVM_EXEC_WORKERS err={}", e),
+            }
+        }
+        if let Ok(queue) = env::var("SVM_EXEC_QUEUE") {
+            match queue.parse() {
+                Ok(queue) => config.engine.queue = queue,
+                Err(e) => error!("ignoring SVM_EXEC_QUEUE err={}", e),
+            }
+        }
+        if let Ok(bytes) = env::var("SVM_MAX_MESSAGE_BYTES") {
+            match bytes.parse() {
+                Ok(bytes) => config.limits.max_message_bytes = bytes,
+                Err(e) => error!("ignoring SVM_MAX_MESSAGE_BYTES err={}", e),
+            }
+        }
+        if let Ok(rate) = env::var("SVM_CONN_RATE") {
+            match rate.parse() {
+                Ok(rate) => config.limits.connection_rate = rate,
+                Err(e) => error!("ignoring SVM_CONN_RATE err={}", e),
+            }
+        }
+        if let Ok(rate) = env::var("SVM_GLOBAL_RATE") {
+            match rate.parse() {
+                Ok(rate) => config.limits.global_rate = rate,
+                Err(e) => error!("ignoring SVM_GLOBAL_RATE err={}", e),
+            }
+        }
+        if let Ok(in_flight) = env::var("SVM_CONN_IN_FLIGHT") {
+            match in_flight.parse() {
+                Ok(in_flight) => config.limits.connection_in_flight = in_flight,
+                Err(e) => error!("ignoring SVM_CONN_IN_FLIGHT err={}", e),
+            }
+        }
+        if let Ok(in_flight) = env::var("SVM_GLOBAL_IN_FLIGHT") {
+            match in_flight.parse() {
+                Ok(in_flight) => config.limits.global_in_flight = in_flight,
+                Err(e) => error!("ignoring SVM_GLOBAL_IN_FLIGHT err={}", e),
+            }
+        }
+        if let Ok(subscriptions) = env::var("SVM_CONN_SUBSCRIPTIONS") {
+            match subscriptions.parse() {
+                Ok(subscriptions) => config.limits.connection_subscriptions = subscriptions,
+                Err(e) => error!("ignoring SVM_CONN_SUBSCRIPTIONS err={}", e),
+            }
+        }
+        if let Ok(subscriptions) = env::var("SVM_GLOBAL_SUBSCRIPTIONS") {
+            match subscriptions.parse() {
+                Ok(subscriptions) => config.limits.global_subscriptions = subscriptions,
+                Err(e) => error!("ignoring SVM_GLOBAL_SUBSCRIPTIONS err={}", e),
+            }
+        }
+        if let Ok(connections) = env::var("SVM_RPC_MAX_CONNECTIONS") {
+            match connections.parse() {
+                Ok(connections) => config.limits.rpc_connections = connections,
+                Err(e) => error!("ignoring SVM_RPC_MAX_CONNECTIONS err={}", e),
+            }
+        }
+        if let Ok(timeout) = env::var("SVM_RPC_IDLE_TIMEOUT_SECS") {
+            match timeout.parse() {
+                Ok(timeout) => config.limits.rpc_idle_timeout = Duration::from_secs(timeout),
+                Err(e) => error!("ignoring SVM_RPC_IDLE_TIMEOUT_SECS err={}", e),
+            }
+        }
+        if let Ok(interval) = env::var("SVM_CHECKPOINT_INTERVAL_SECS") {
+            match interval.parse() {
+                Ok(interval) => config.checkpoints.interval = Duration::from_secs(interval),
+                Err(e) => error!("ignoring SVM_CHECKPOINT_INTERVAL_SECS err={}", e),
+            }
+        }
+        if let Ok(keep) = env::var("SVM_CHECKPOINTS_KEPT") {
+            match keep.parse() {
+                Ok(keep) => config.checkpoints.keep = keep,
+                Err(e) => error!("ignoring SVM_CHECKPOINTS_KEPT err={}", e),
+            }
+        }
+        config
+    }
+}
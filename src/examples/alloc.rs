@@ -21,7 +21,7 @@ pub async fn alloc_incremental(tm: Arc<SVMMemory>, a: u32, b: u32) {
             }
         });
     }
-    while let Some(_) = set.join_next().await {}
+    while set.join_next().await.is_some() {}
     info!(
         "finish allocation elapesed_microsec={}",
         now.elapsed().as_micros()
@@ -33,12 +33,14 @@ pub async fn alloc_duangua(tm: Arc<SVMMemory>, a: u32, b: u32) {
     let tm = tm.clone();
     let keya = format!("0x{}", a);
     let keyb = format!("0x{}", b);
-    if let Err(_) = retry_transaction(tm, |txn| {
+    if retry_transaction(tm, |txn| {
         let alloc_amt = SVMPrimitives::U24(0);
         txn.write(keya.as_bytes().to_vec(), alloc_amt.clone());
         txn.write(keyb.as_bytes().to_vec(), alloc_amt.clone());
         Ok(alloc_amt)
-    }) {
+    })
+    .is_err()
+    {
         error!(
             "fuck failed allocation duangua elapesed_microsec={}",
             now.elapsed().as_micros()
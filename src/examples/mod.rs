@@ -1,10 +1,8 @@
 use crate::block_stm::get_val;
-use crate::examples::query::query;
 use crate::{block_stm::svm_memory::SVMMemory, svm::svm::SVM};
 use log::{error, info};
-use make_move::{make_move, make_move3};
+use make_move::make_move3;
 use std::sync::Arc;
-use transfer::reverse_transfer;
 
 pub mod alloc;
 pub mod make_move;
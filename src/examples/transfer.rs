@@ -9,6 +9,9 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 use tokio::{task::JoinSet, time::Instant};
 
+/// tx id -> (vm, memory, backoff) microseconds
+type TxTimers = Arc<RwLock<HashMap<u64, (u128, u128, u128)>>>;
+
 pub async fn transfer(tm: Arc<SVMMemory>, svm: Arc<SVM>, a: u32, b: u32) {
     let now = Instant::now();
 
@@ -48,19 +51,19 @@ pub async fn transfer(tm: Arc<SVMMemory>, svm: Arc<SVM>, a: u32, b: u32) {
                                 let (from_val, to_val) = (els[0].clone(), els[1].clone());
                                 txn.write(from_key_vec.clone(), from_val);
                                 txn.write(to_key_vec.clone(), to_val);
-                                return Ok(result);
+                                Ok(result)
                             }
-                            _ => return Err(format!("unexpected type of result term={:#?}", term)),
-                        };
+                            _ => Err(format!("unexpected type of result term={:#?}", term)),
+                        }
                     }
-                    Err(e) => return Err(format!("svm execution failed err={}", e)),
-                };
+                    Err(e) => Err(format!("svm execution failed err={}", e)),
+                }
             }) {
                 error!("from_key={} err={}", from_key.clone(), e);
             }
         });
     }
-    while let Some(_) = set.join_next().await {}
+    while set.join_next().await.is_some() {}
     info!(
         "finish transfer elapesed_microsec={}",
         now.elapsed().as_micros()
@@ -70,8 +73,7 @@ pub async fn transfer(tm: Arc<SVMMemory>, svm: Arc<SVM>, a: u32, b: u32) {
 pub async fn reverse_transfer(tm: Arc<SVMMemory>, svm: Arc<SVM>, a: u32, b: u32) {
     let now = Instant::now();
     let mut set = JoinSet::new();
-    let txs_timers: Arc<RwLock<HashMap<u64, (u128, u128, u128)>>> =
-        Arc::new(RwLock::new(HashMap::new()));
+    let txs_timers: TxTimers = Arc::new(RwLock::new(HashMap::new()));
 
     let mut total_txs = 0;
 
@@ -103,7 +105,7 @@ pub async fn reverse_transfer(tm: Arc<SVMMemory>, svm: Arc<SVM>, a: u32, b: u32)
             });
         }
     }
-    while let Some(_) = set.join_next().await {}
+    while set.join_next().await.is_some() {}
     info!(
         "finish transfering total_txs={} elapesed_microsec={}",
         total_txs,
@@ -111,7 +113,7 @@ pub async fn reverse_transfer(tm: Arc<SVMMemory>, svm: Arc<SVM>, a: u32, b: u32)
     );
     {
         let mut stats_content = String::from("");
-        stats_content.push_str(&format!("i,j,vm_mrs,mem_mrs,backoff_mrs\n"));
+        stats_content.push_str("i,j,vm_mrs,mem_mrs,backoff_mrs\n");
         let stats = txs_timers.read().await;
         for (txid, timers) in stats.iter() {
             let i = txid >> 32;
@@ -0,0 +1,159 @@
+use super::pool::ComputePool;
+use std::{fmt, sync::Arc, thread};
+use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore};
+
+#[derive(Clone, Debug)]
+pub struct EngineConfig {
+    /// threads running transactions, one per core by default
+    pub workers: usize,
+    /// transactions and other jobs admitted but not done yet, more are
+    /// turned away as busy
+    pub queue: usize,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            workers: thread::available_parallelism().map_or(1, |n| n.get()),
+            queue: 10_000,
+        }
+    }
+}
+
+/// The engine's queue has no room for the work; the client should retry
+/// later.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Busy {
+    pub wanted: usize,
+    pub available: usize,
+    pub capacity: usize,
+}
+
+impl fmt::Display for Busy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "execution queue is full wanted={} available={} capacity={}",
+            self.wanted, self.available, self.capacity
+        )
+    }
+}
+
+/// Runs transactions and other CPU-heavy work on a sized pool of threads of
+/// its own. Async code only talks to it through channels: work is handed
+/// over through a bounded queue and results come back on oneshot channels,
+/// so a flood of requests gets `Busy` instead of piling up.
+pub struct ExecutionEngine {
+    pool: ComputePool,
+    admitted: Arc<Semaphore>,
+    capacity: usize,
+}
+
+impl ExecutionEngine {
+    pub fn new(config: &EngineConfig) -> Self {
+        let capacity = config.queue.max(1);
+        Self {
+            pool: ComputePool::new(config.workers),
+            admitted: Arc::new(Semaphore::new(capacity)),
+            capacity,
+        }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.pool.workers()
+    }
+
+    /// Most jobs admitted at once.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Jobs admitted and not done yet.
+    pub fn queued(&self) -> usize {
+        self.capacity - self.admitted.available_permits()
+    }
+
+    /// Makes room for `jobs` more jobs, held until the permit is dropped.
+    pub fn admit(&self, jobs: usize) -> Result<OwnedSemaphorePermit, Busy> {
+        let busy = || Busy {
+            wanted: jobs,
+            available: self.admitted.available_permits(),
+            capacity: self.capacity,
+        };
+        let jobs = u32::try_from(jobs).map_err(|_| busy())?;
+        self.admitted
+            .clone()
+            .try_acquire_many_owned(jobs)
+            .map_err(|_| busy())
+    }
+
+    /// Runs `f` on the pool if the queue has room for it.
+    pub async fn try_run<T, F>(&self, f: F) -> Result<Result<T, String>, Busy>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let admitted = self.admit(1)?;
+        let result = self.pool.run(f).await;
+        drop(admitted);
+        Ok(result)
+    }
+
+    /// Queues `f` for work that is already admitted.
+    pub fn spawn<T, F>(&self, f: F) -> oneshot::Receiver<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.pool.spawn(f)
+    }
+
+    /// Runs `f` for work that is already admitted.
+    pub async fn run<T, F>(&self, f: F) -> Result<T, String>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.pool.run(f).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Mutex};
+
+    #[tokio::test]
+    async fn full_queue_turns_work_away() {
+        let engine = Arc::new(ExecutionEngine::new(&EngineConfig {
+            workers: 1,
+            queue: 3,
+        }));
+        let submitted = engine.admit(2).unwrap();
+        assert_eq!(engine.queued(), 2);
+
+        let (release, released) = mpsc::channel::<()>();
+        let released = Mutex::new(released);
+        let running = {
+            let engine = engine.clone();
+            tokio::spawn(async move {
+                engine
+                    .try_run(move || released.lock().unwrap().recv().unwrap())
+                    .await
+            })
+        };
+        while engine.queued() < 3 {
+            tokio::task::yield_now().await;
+        }
+        let busy = engine.try_run(|| ()).await.unwrap_err();
+        assert_eq!((busy.wanted, busy.available, busy.capacity), (1, 0, 3));
+        assert!(engine.admit(1).is_err());
+
+        release.send(()).unwrap();
+        assert!(running.await.unwrap().unwrap().is_ok());
+        drop(submitted);
+        assert_eq!(engine.queued(), 0);
+        assert_eq!(engine.try_run(|| 7).await, Ok(Ok(7)));
+        assert!(engine.admit(4).is_err());
+    }
+}
@@ -1,10 +1,14 @@
-use crate::block_stm::svm_memory::{retry_transaction, SVMMemory};
+use crate::block_stm::svm_memory::{execute_transaction, Committed, SVMMemory, Transaction};
 use crate::svm::{primitive_types::SVMPrimitives, svm::SVM};
 use bend::fun::Term;
-use log::info;
-use std::sync::Arc;
-use types::TxBody;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use types::{Receipt, TxBody};
 
+pub mod engine;
+mod pool;
 pub mod types;
 
 pub fn process_tx(
@@ -12,47 +16,148 @@ pub fn process_tx(
     tm: Arc<SVMMemory>,
     svm: Arc<SVM>,
 ) -> Result<SVMPrimitives, std::string::String> {
+    execute_tx(tx_body, tm, svm)
+        .result
+        .map(|committed| committed.ret_value)
+}
+
+/// Result of [`execute_tx`].
+pub struct Executed {
+    pub result: Result<Committed, String>,
+    /// HVM interactions of the last execution
+    pub gas: u64,
+}
+
+impl Executed {
+    pub fn receipt(&self, tx_body: &TxBody) -> Receipt {
+        let keys = |keys: &[Vec<u8>]| {
+            keys.iter()
+                .map(|key| String::from_utf8_lossy(key).into_owned())
+                .collect()
+        };
+        let (ret_value, errs, retries, read_set, write_set, write_hash) = match &self.result {
+            Ok(committed) => (
+                Some(committed.ret_value.clone()),
+                None,
+                committed.retries,
+                keys(&committed.reads),
+                keys(&committed.writes),
+                hex::encode(committed.write_hash),
+            ),
+            Err(e) => (None, Some(e.clone()), 0, vec![], vec![], String::new()),
+        };
+        Receipt {
+            tx_hash: tx_body.tx_hash.clone(),
+            code_hash: tx_body.code_hash.clone(),
+            status: self.result.is_ok(),
+            ret_value,
+            errs,
+            gas: self.gas,
+            retries,
+            read_set,
+            write_set,
+            write_hash,
+        }
+    }
+}
+
+/// Runs `tx_body` like [`process_tx`], also reporting where it committed and
+/// what it cost.
+pub fn execute_tx(tx_body: TxBody, tm: Arc<SVMMemory>, svm: Arc<SVM>) -> Executed {
+    let gas = AtomicU64::new(0);
     let tm = tm.clone();
     let svm = svm.clone();
+    let mode = svm.concurrency_mode(&tx_body.code_hash);
+    let keys: Vec<Vec<u8>> = tx_body
+        .objs
+        .iter()
+        .map(|obj| obj.as_bytes().to_vec())
+        .collect();
 
-    let result = retry_transaction(tm, |txn| {
-        let mut objects = vec![];
-        for obj_hash in tx_body.objs.clone() {
-            let object = match txn.read(obj_hash.as_bytes().to_vec()) {
-                Some(object) => object,
-                None => return Err(format!("key={} does not exist", obj_hash)),
-            };
-            objects.push(object)
+    let result = execute_transaction(tm, mode, &keys, |txn| run_tx(txn, &tx_body, &svm, &gas));
+
+    Executed {
+        result,
+        gas: gas.into_inner(),
+    }
+}
+
+/// Runs `tx_body` on the latest state without committing it, to preview its
+/// result and cost. Nothing is written and no other transaction waits on it.
+pub fn simulate_tx(tx_body: &TxBody, tm: Arc<SVMMemory>, svm: Arc<SVM>) -> Receipt {
+    let gas = AtomicU64::new(0);
+    let mut txn = Transaction::new(&tm);
+    let result = run_tx(&mut txn, tx_body, &svm, &gas);
+    let keys = |keys: Vec<Vec<u8>>| {
+        keys.into_iter()
+            .map(|key| String::from_utf8_lossy(&key).into_owned())
+            .collect()
+    };
+    let (read_set, write_set, write_hash) = match &result {
+        Ok(_) => {
+            let (reads, writes) = txn.keys();
+            (keys(reads), keys(writes), hex::encode(txn.write_hash()))
         }
-        let mut args = objects;
-        args.extend_from_slice(&tx_body.args);
-
-        // due to limitations of HVM, we cannot read data from this code
-        // however, we can feed the data from arguments
-        // so arguments of main is the thing we want to modify PLUS the actual arguments.
-        let args: Vec<Term> = args.iter().map(|arg| arg.to_term()).collect();
-        match svm.clone().run_code(&tx_body.code_hash, Some(args)) {
-            Ok((term, _stats, _diags)) => {
-                let result = SVMPrimitives::from_term(term.clone());
-                match result {
-                    SVMPrimitives::Tup(ref els) => {
-                        // VM always returned the (un)modified objects as in the order
-                        // of receiving in input. We write back to SVMMemmory.
-                        let modified_objs = els.clone();
-                        for (index, obj_hash) in tx_body.objs.iter().enumerate() {
-                            txn.write(obj_hash.as_bytes().to_vec(), modified_objs[index].clone());
-                        }
-                        return Ok(result);
+        Err(_) => (vec![], vec![], String::new()),
+    };
+    Receipt {
+        tx_hash: tx_body.tx_hash.clone(),
+        code_hash: tx_body.code_hash.clone(),
+        status: result.is_ok(),
+        ret_value: result.as_ref().ok().cloned(),
+        errs: result.err(),
+        gas: gas.into_inner(),
+        retries: 0,
+        read_set,
+        write_set,
+        write_hash,
+    }
+}
+
+/// Runs `tx_body` in `txn`: reads its objects, runs its code on them and the
+/// arguments, and writes back the objects the code returns.
+fn run_tx(
+    txn: &mut Transaction,
+    tx_body: &TxBody,
+    svm: &Arc<SVM>,
+    gas: &AtomicU64,
+) -> Result<SVMPrimitives, String> {
+    if !svm.has_code(&tx_body.code_hash) {
+        return Err(format!("code id={} is not deployed", tx_body.code_hash));
+    }
+    txn.attach_tx(tx_body);
+    let mut objects = vec![];
+    for obj_hash in tx_body.objs.clone() {
+        let object = match txn.read(obj_hash.as_bytes().to_vec()) {
+            Some(object) => object,
+            None => return Err(format!("key={} does not exist", obj_hash)),
+        };
+        objects.push(object)
+    }
+    let mut args = objects;
+    args.extend_from_slice(&tx_body.args);
+
+    // due to limitations of HVM, we cannot read data from this code
+    // however, we can feed the data from arguments
+    // so arguments of main is the thing we want to modify PLUS the actual arguments.
+    let args: Vec<Term> = args.iter().map(|arg| arg.to_term()).collect();
+    match svm.clone().run_code(&tx_body.code_hash, Some(args)) {
+        Ok((term, stats, _diags)) => {
+            gas.store(stats.interactions, Ordering::Relaxed);
+            let result = SVMPrimitives::from_term(term.clone());
+            match result {
+                SVMPrimitives::Tup(ref els) => {
+                    // VM always returned the (un)modified objects as in the order
+                    // of receiving in input. We write back to SVMMemmory.
+                    let modified_objs = els.clone();
+                    for (index, obj_hash) in tx_body.objs.iter().enumerate() {
+                        txn.write(obj_hash.as_bytes().to_vec(), modified_objs[index].clone());
                     }
-                    _ => return Err(format!("unexpected type of result term={:#?}", term)),
-                };
+                    Ok(result)
+                }
+                _ => Err(format!("unexpected type of result term={:#?}", term)),
             }
-            Err(e) => Err(format!("svm execution failed err={}", e)),
         }
-    });
-
-    match result {
-        Ok(res) => Ok(res),
-        Err(e) => Err(e),
+        Err(e) => Err(format!("svm execution failed err={}", e)),
     }
 }
@@ -0,0 +1,73 @@
+use log::error;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+use tokio::sync::oneshot;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Threads of its own for CPU-heavy work such as running transactions, so
+/// it never holds up the tokio workers serving sockets. Jobs run in the
+/// order they are queued, on the first idle thread.
+pub struct ComputePool {
+    jobs: mpsc::Sender<Job>,
+    workers: usize,
+}
+
+impl ComputePool {
+    pub fn new(workers: usize) -> Self {
+        let workers = workers.max(1);
+        let (jobs, queued) = mpsc::channel::<Job>();
+        let queued = Arc::new(Mutex::new(queued));
+        for index in 0..workers {
+            let queued = queued.clone();
+            thread::Builder::new()
+                .name(format!("compute-{}", index))
+                .spawn(move || loop {
+                    let job = match queued.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        // the pool is dropped
+                        Err(_) => return,
+                    };
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        error!("compute job panicked");
+                    }
+                })
+                .expect("failed to start compute thread");
+        }
+        Self { jobs, workers }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Queues `f`, whose result is sent on the returned channel. The channel
+    /// closes without a result if `f` panics.
+    pub fn spawn<T, F>(&self, f: F) -> oneshot::Receiver<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (done, result) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            _ = done.send(f());
+        });
+        // the workers only stop once `jobs` is dropped
+        _ = self.jobs.send(job);
+        result
+    }
+
+    /// Runs `f` on the pool and waits for its result.
+    pub async fn run<T, F>(&self, f: F) -> Result<T, String>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn(f)
+            .await
+            .map_err(|_| "compute job panicked".to_string())
+    }
+}
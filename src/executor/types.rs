@@ -1,7 +1,8 @@
+use crate::codec;
 use crate::svm::primitive_types::SVMPrimitives;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct TxBody {
     pub tx_hash: String,
     /// the code hash
@@ -12,7 +13,22 @@ pub struct TxBody {
     pub args: Vec<SVMPrimitives>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl TxBody {
+    /// Hex hash of the canonical encoding of what the transaction runs, every
+    /// field but `tx_hash` itself.
+    pub fn canonical_hash(&self) -> String {
+        hex::encode(codec::hash(&(&self.code_hash, &self.objs, &self.args)))
+    }
+
+    /// Gives a transaction submitted without a `tx_hash` its canonical hash.
+    pub fn fill_hash(&mut self) {
+        if self.tx_hash.is_empty() {
+            self.tx_hash = self.canonical_hash();
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct TxResult {
     pub tx_hash: String,
     pub code_hash: String,
@@ -20,3 +36,67 @@ pub struct TxResult {
     pub ret_value: Option<SVMPrimitives>,
     pub errs: Option<String>,
 }
+
+impl TxResult {
+    pub fn from_result(tx_body: &TxBody, result: &Result<SVMPrimitives, String>) -> Self {
+        match result {
+            Ok(ret_val) => TxResult {
+                tx_hash: tx_body.tx_hash.clone(),
+                code_hash: tx_body.code_hash.clone(),
+                status: true,
+                ret_value: Some(ret_val.clone()),
+                errs: None,
+            },
+            Err(e) => TxResult {
+                tx_hash: tx_body.tx_hash.clone(),
+                code_hash: tx_body.code_hash.clone(),
+                status: false,
+                ret_value: None,
+                errs: Some(e.clone()),
+            },
+        }
+    }
+}
+
+/// Everything known about an executed transaction, kept with its block.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Receipt {
+    pub tx_hash: String,
+    pub code_hash: String,
+    pub status: bool,
+    pub ret_value: Option<SVMPrimitives>,
+    pub errs: Option<String>,
+    /// HVM interactions of the execution that produced the result
+    pub gas: u64,
+    /// aborted attempts before the commit
+    pub retries: u32,
+    /// objects the committed execution read and wrote
+    pub read_set: Vec<String>,
+    pub write_set: Vec<String>,
+    /// hex hash of the values the committed execution wrote, empty if it
+    /// failed
+    pub write_hash: String,
+}
+
+impl Receipt {
+    pub fn to_tx_result(&self) -> TxResult {
+        TxResult {
+            tx_hash: self.tx_hash.clone(),
+            code_hash: self.code_hash.clone(),
+            status: self.status,
+            ret_value: self.ret_value.clone(),
+            errs: self.errs.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TxStatus {
+    /// waiting for its block to be sealed
+    Pending,
+    Committed,
+    Failed,
+    /// never submitted to this node
+    Unknown,
+}
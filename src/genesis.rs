@@ -0,0 +1,193 @@
+use crate::block_stm::svm_memory::{retry_transaction, SVMMemory};
+use crate::merkle::{tree::StateTree, Hash};
+use crate::storage::StoredObject;
+use crate::svm::{object::SVMObject, primitive_types::SVMPrimitives, svm::SVM};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path, sync::Arc};
+
+/// Read at startup when `SVM_GENESIS` is not set, if it exists.
+pub const DEFAULT_GENESIS_FILE: &str = "genesis.json";
+
+/// Objects written per transaction when applying the genesis state.
+const APPLY_CHUNK: usize = 4096;
+
+/// Initial state and parameters of a chain, read from a JSON file.
+///
+/// ```json
+/// {
+///   "chain": { "chain_id": "local", "block_max_txs": 1000 },
+///   "codes": [{ "id": "0xcounter", "path": "codes/counter.bend" }],
+///   "objects": [{ "addr": "0xpot", "value": { "Tup": [{ "U24": 0 }, "Era"] } }],
+///   "ranges": [{ "from": 0, "to": 1000, "value": { "U24": 100 } }]
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Genesis {
+    #[serde(default)]
+    pub chain: ChainParams,
+    /// codes deployed at boot next to the builtin ones
+    #[serde(default)]
+    pub codes: Vec<GenesisCode>,
+    #[serde(default)]
+    pub objects: Vec<GenesisObject>,
+    #[serde(default)]
+    pub ranges: Vec<GenesisRange>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ChainParams {
+    #[serde(default)]
+    pub chain_id: String,
+    /// override the node's block settings, so every node seals the same blocks
+    #[serde(default)]
+    pub block_max_txs: Option<usize>,
+    #[serde(default)]
+    pub block_interval_ms: Option<u64>,
+}
+
+/// Bend source of a code, inline or in a file relative to the genesis file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenesisCode {
+    pub id: String,
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenesisObject {
+    pub addr: String,
+    pub value: SVMPrimitives,
+}
+
+/// Objects at addresses `0x{from}` to `0x{to}`, both included.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenesisRange {
+    pub from: u32,
+    pub to: u32,
+    /// value of every object in the range, each its own index if omitted
+    #[serde(default)]
+    pub value: Option<SVMPrimitives>,
+}
+
+impl Genesis {
+    /// Reads a genesis file, inlining the source of every code it deploys.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read genesis path={} err={}", path.display(), e))?;
+        let mut genesis: Genesis = serde_json::from_str(&json)
+            .map_err(|e| format!("invalid genesis path={} err={}", path.display(), e))?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for code in &mut genesis.codes {
+            if code.source.is_some() {
+                continue;
+            }
+            let Some(code_path) = &code.path else {
+                return Err(format!("code id={} has neither source nor path", code.id));
+            };
+            let source = fs::read_to_string(dir.join(code_path))
+                .map_err(|e| format!("failed to read code id={} err={}", code.id, e))?;
+            code.source = Some(source);
+        }
+        Ok(genesis)
+    }
+
+    /// Every initial object at version 1, in key order. Explicit objects win
+    /// over ranges covering the same address.
+    pub fn state(&self) -> Vec<StoredObject> {
+        let mut state = BTreeMap::new();
+        for range in &self.ranges {
+            for i in range.from..=range.to {
+                let value = range.value.clone().unwrap_or(SVMPrimitives::U24(i));
+                state.insert(format!("0x{}", i).into_bytes(), value);
+            }
+        }
+        for object in &self.objects {
+            state.insert(object.addr.clone().into_bytes(), object.value.clone());
+        }
+        state
+            .into_iter()
+            .map(|(key, value)| (key, SVMObject { value, version: 1 }))
+            .collect()
+    }
+
+    /// Root of the initial state, what a fresh node reports after boot.
+    pub fn state_root(&self) -> Hash {
+        StateTree::from_objects(&self.state()).root()
+    }
+
+    /// Deploys the genesis codes on `svm`.
+    pub fn deploy(&self, svm: SVM) -> Result<SVM, String> {
+        self.codes.iter().try_fold(svm, |svm, code| {
+            let source = code.source.as_deref().unwrap_or_default();
+            svm.with_code(&code.id, source)
+        })
+    }
+
+    /// Writes the initial objects to `tm`. On a fresh node they get version 1,
+    /// otherwise they overwrite whatever the objects hold now.
+    pub fn apply(&self, tm: &Arc<SVMMemory>) -> Result<usize, String> {
+        let state = self.state();
+        for chunk in state.chunks(APPLY_CHUNK) {
+            retry_transaction(tm.clone(), |txn| {
+                for (key, object) in chunk {
+                    txn.write(key.clone(), object.value.clone());
+                }
+                Ok(SVMPrimitives::Era)
+            })?;
+        }
+        Ok(state.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn genesis_state_is_reproducible() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("genesis.json");
+        fs::write(dir.path().join("counter.bend"), "def main(): return 0").unwrap();
+        fs::write(
+            &path,
+            r#"{
+                "chain": { "chain_id": "test", "block_max_txs": 10 },
+                "codes": [{ "id": "0xcounter", "path": "counter.bend" }],
+                "objects": [
+                    { "addr": "0x3", "value": { "Tup": [{ "U24": 1 }, "Era"] } },
+                    { "addr": "0xpot", "value": { "U24": 7 } }
+                ],
+                "ranges": [{ "from": 0, "to": 9999 }]
+            }"#,
+        )
+        .unwrap();
+
+        let genesis = Genesis::load(&path).unwrap();
+        assert_eq!(genesis.chain.block_max_txs, Some(10));
+        assert_eq!(
+            genesis.codes[0].source.as_deref(),
+            Some("def main(): return 0")
+        );
+        let state = genesis.state();
+        assert_eq!(state.len(), 10_001);
+
+        let tm = Arc::new(SVMMemory::new());
+        assert_eq!(genesis.apply(&tm).unwrap(), 10_001);
+        assert_eq!(tm.state_root(), genesis.state_root());
+        assert_eq!(
+            tm.get(b"0x42".to_vec()).unwrap().value,
+            SVMPrimitives::U24(42)
+        );
+        assert_eq!(
+            tm.get(b"0x3".to_vec()).unwrap().value,
+            SVMPrimitives::Tup(vec![SVMPrimitives::U24(1), SVMPrimitives::Era])
+        );
+
+        let other = Arc::new(SVMMemory::new());
+        Genesis::load(&path).unwrap().apply(&other).unwrap();
+        assert_eq!(other.state_root(), tm.state_root());
+    }
+}
@@ -1,24 +1,242 @@
-use crate::examples::run_example;
-use block_stm::svm_memory::SVMMemory;
-use examples::alloc;
-use std::sync::Arc;
-use svm::svm::SVM;
+use block::{
+    builder::BlockProducer,
+    replay::{read_tx_log, replay, ReplayBatch, ReplayMode},
+    store::{read_blocks, BlockStore, BLOCKS_FILE},
+};
+use block_stm::svm_memory::{ConcurrencyMode, SVMMemory};
+use config::NodeConfig;
+use executor::{engine::ExecutionEngine, execute_tx};
+use genesis::{Genesis, DEFAULT_GENESIS_FILE};
+use log::{error, info, warn};
+use std::{
+    io,
+    path::Path,
+    process,
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+use storage::{
+    checkpoint::run_checkpoints,
+    export::{export_snapshot, import_snapshot, SNAPSHOTS_DIR},
+};
+use svm::{codes::load_codes, svm::SVM};
+use ws::{auth::AdminAuth, limits::Limits};
 
+pub mod block;
 pub mod block_stm;
+pub mod codec;
+pub mod config;
 pub mod examples;
 pub mod executor;
+pub mod genesis;
+pub mod merkle;
+pub mod rpc;
+pub mod storage;
 pub mod svm;
+/// Transactions and nodes shared by the tests of several modules.
+#[cfg(test)]
+pub(crate) mod test_support;
 pub mod ws;
 
+const USAGE: &str = r#"usage:
+  subnet_vm [run]          run the node
+  subnet_vm export <file>  write the state in SVM_DATA_DIR to a snapshot file
+  subnet_vm import <file>  seed an empty SVM_DATA_DIR from a snapshot file
+  subnet_vm replay <snapshot> <log> [sequential|parallel]
+                           re-execute a block log, or a .jsonl file of TxBody,
+                           on top of a snapshot and check the recorded results"#;
+
 #[tokio::main]
 async fn main() {
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
+    let config = NodeConfig::from_env();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        [] | ["run"] => run_node(config).await,
+        ["export", file] => {
+            let tm = open_memory(&config, true);
+            match export_snapshot(&tm, Path::new(file)) {
+                Ok(header) => info!("exported snapshot={:?}", header),
+                Err(e) => exit_with(&format!("export failed err={}", e)),
+            }
+        }
+        ["import", file] => {
+            let tm = open_memory(&config, true);
+            match import_snapshot(&tm, Path::new(file)) {
+                Ok(header) => info!("imported snapshot={:?}", header),
+                Err(e) => exit_with(&format!("import failed err={}", e)),
+            }
+        }
+        ["replay", snapshot, log] => replay_log(&config, snapshot, log, ReplayMode::default()),
+        ["replay", snapshot, log, mode] => match mode.parse() {
+            Ok(mode) => replay_log(&config, snapshot, log, mode),
+            Err(e) => exit_with(&e),
+        },
+        _ => exit_with(USAGE),
+    }
+}
+
+async fn run_node(mut config: NodeConfig) {
+    let genesis = Arc::new(load_genesis(&config));
+    if let Some(max_txs) = genesis.chain.block_max_txs {
+        config.blocks.max_txs = max_txs;
+    }
+    if let Some(interval) = genesis.chain.block_interval_ms {
+        config.blocks.interval = Duration::from_millis(interval);
+    }
+
+    let tm = Arc::new(open_memory(&config, false));
+    let svm = Arc::new(build_svm(&config, &genesis));
+
+    // state restored from storage is kept, only a fresh node gets the genesis state
+    if tm.is_empty() {
+        match genesis.apply(&tm) {
+            Ok(objects) => info!(
+                "applied genesis chain_id={} objects={} state_root={}",
+                genesis.chain.chain_id,
+                objects,
+                hex::encode(tm.state_root())
+            ),
+            Err(e) => exit_with(&format!("failed to apply genesis err={}", e)),
+        }
+    }
+
+    let store = Arc::new(match &config.data_dir {
+        Some(data_dir) => BlockStore::open(&data_dir.join(BLOCKS_FILE), config.fsync)
+            .expect("failed to open block store"),
+        None => BlockStore::default(),
+    });
+    let engine = Arc::new(ExecutionEngine::new(&config.engine));
+    info!(
+        "execution engine workers={} queue={}",
+        engine.workers(),
+        engine.capacity()
+    );
+    let blocks = Arc::new(BlockProducer::spawn(
+        tm.clone(),
+        svm.clone(),
+        store,
+        config.blocks.clone(),
+        engine.clone(),
+    ));
+
+    tokio::spawn(block_stm::run_gc(tm.clone(), Duration::from_secs(10)));
+    if let Some(data_dir) = &config.data_dir {
+        tokio::spawn(run_checkpoints(
+            tm.clone(),
+            data_dir.clone(),
+            config.checkpoints.clone(),
+        ));
+    }
+
+    // examples::run_example(tm.clone(), svm.clone(), 0, 100).await;
+
+    let admin = match &config.admin_token_file {
+        Some(path) => AdminAuth::load(path).unwrap_or_else(|e| {
+            exit_with(&format!(
+                "failed to read admin token file={} err={}",
+                path.display(),
+                e
+            ))
+        }),
+        None => {
+            warn!("no admin token file, admin messages are refused");
+            AdminAuth::default()
+        }
+    };
+    let node = Arc::new(ws::Node {
+        tm,
+        svm,
+        engine,
+        blocks,
+        genesis,
+        snapshot_dir: config
+            .snapshot_dir
+            .or_else(|| (config.data_dir.as_ref()).map(|data_dir| data_dir.join(SNAPSHOTS_DIR))),
+        data_dir: config.data_dir,
+        limits: Arc::new(Limits::new(config.limits)),
+        admin,
+        paused: AtomicBool::new(false),
+    });
+    if let Some(addr) = config.rpc_addr {
+        let node = node.clone();
+        tokio::spawn(async move { rpc::run_rpc(&addr, node).await });
+    }
+    ws::run_ws(&config.ws_addr, node).await;
+}
+
+/// Reads `config.genesis`, or the default genesis file when there is one.
+fn load_genesis(config: &NodeConfig) -> Genesis {
+    let path = match &config.genesis {
+        Some(path) => path.as_path(),
+        None if Path::new(DEFAULT_GENESIS_FILE).exists() => Path::new(DEFAULT_GENESIS_FILE),
+        None => {
+            warn!("no genesis file, starting without objects");
+            return Genesis::default();
+        }
+    };
+    Genesis::load(path).unwrap_or_else(|e| exit_with(&e))
+}
+
+/// The SVM with the genesis codes, and the codes deployed since that are
+/// kept in `config.data_dir`.
+fn build_svm(config: &NodeConfig, genesis: &Genesis) -> SVM {
+    let svm = config.locking_codes.iter().fold(
+        SVM::new().with_default_mode(config.concurrency_mode),
+        |svm, code| svm.with_code_mode(code, ConcurrencyMode::Locking),
+    );
+    let svm = genesis.deploy(svm).unwrap_or_else(|e| exit_with(&e));
+    let Some(data_dir) = &config.data_dir else {
+        return svm;
+    };
+    let codes = load_codes(data_dir)
+        .unwrap_or_else(|e| exit_with(&format!("failed to read deployed codes err={}", e)));
+    codes
+        .iter()
+        .try_fold(svm, |svm, (code_id, source)| svm.with_code(code_id, source))
+        .unwrap_or_else(|e| exit_with(&e))
+}
+
+/// Re-executes the transactions in `log` on a fresh in-memory node seeded
+/// from `snapshot`, exiting with an error at the first divergence.
+fn replay_log(config: &NodeConfig, snapshot: &str, log: &str, mode: ReplayMode) {
+    let log = Path::new(log);
+    let batches: io::Result<Vec<ReplayBatch>> = match log.extension() {
+        Some(ext) if ext == "jsonl" => read_tx_log(log).map(|batch| vec![batch]),
+        _ => read_blocks(log).map(|blocks| blocks.into_iter().map(ReplayBatch::from).collect()),
+    };
+    let batches = batches.unwrap_or_else(|e| exit_with(&format!("failed to read log err={}", e)));
+
     let tm = Arc::new(SVMMemory::new());
-    let svm = Arc::new(SVM::new());
-    let addr = "0.0.0.0:9001";
+    if let Err(e) = import_snapshot(&tm, Path::new(snapshot)) {
+        exit_with(&format!("failed to import genesis snapshot err={}", e));
+    }
+    let svm = Arc::new(build_svm(config, &load_genesis(config)));
+    let report = replay(&tm, batches, mode, |tx_body| {
+        execute_tx(tx_body.clone(), tm.clone(), svm.clone())
+    });
+
+    info!("replayed {}", serde_json::to_string(&report).unwrap());
+    if let Some(divergence) = report.divergence {
+        exit_with(&format!("replay diverged {:?}", divergence));
+    }
+}
 
-    // run_example(tm.clone(), svm.clone(), 0, 100).await;
+/// Restores the memory from `config.data_dir`, or starts empty without one.
+fn open_memory(config: &NodeConfig, require_data_dir: bool) -> SVMMemory {
+    let tm = SVMMemory::new().with_retention(config.history_retention);
+    match &config.data_dir {
+        Some(data_dir) => {
+            storage::open_durable(tm, data_dir, config.fsync).expect("failed to restore state")
+        }
+        None if require_data_dir => exit_with("SVM_DATA_DIR must be set"),
+        None => tm,
+    }
+}
 
-    ws::run_ws(&addr, tm, svm).await;
+fn exit_with(message: &str) -> ! {
+    error!("{}", message);
+    process::exit(2)
 }
@@ -0,0 +1,78 @@
+use crate::codec;
+use crate::svm::{object::Version, primitive_types::SVMPrimitives};
+use sha2::{Digest, Sha256};
+
+pub mod proof;
+pub mod tree;
+
+pub type Hash = [u8; 32];
+
+/// Root of a state with no objects, and hash of every empty subtree.
+pub const EMPTY_HASH: Hash = [0; 32];
+
+/// The tree has `1 << BUCKET_BITS` buckets, a key goes to the one named by
+/// the first bits of its hash.
+pub const BUCKET_BITS: u32 = 16;
+
+const LEAF_TAG: u8 = 0;
+const BUCKET_TAG: u8 = 1;
+const NODE_TAG: u8 = 2;
+const WRITES_TAG: u8 = 3;
+
+pub fn bucket_of(key: &[u8]) -> usize {
+    let digest = Sha256::digest(key);
+    (u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) >> (32 - BUCKET_BITS))
+        as usize
+}
+
+/// Hash of an object, over the canonical encoding of its key, version and
+/// value.
+pub fn leaf_hash(key: &[u8], version: Version, value: &SVMPrimitives) -> Hash {
+    let mut bytes = vec![LEAF_TAG];
+    bytes.extend_from_slice(&codec::encode(&(key, version, value)));
+    Sha256::digest(&bytes).into()
+}
+
+/// Hash of the `(key, value)` pairs a transaction wrote, which must be in
+/// key order. Runs writing the same values hash the same.
+pub fn writes_hash<'a, I>(writes: I) -> Hash
+where
+    I: IntoIterator<Item = (&'a Vec<u8>, &'a SVMPrimitives)>,
+{
+    let mut bytes = vec![WRITES_TAG];
+    for write in writes {
+        bytes.extend_from_slice(&codec::encode(&write));
+    }
+    Sha256::digest(&bytes).into()
+}
+
+/// Hash of a bucket's `(key, leaf hash)` entries, which must be in key order.
+pub fn bucket_hash<'a, I>(entries: I) -> Hash
+where
+    I: IntoIterator<Item = (&'a Vec<u8>, &'a Hash)>,
+{
+    let mut hasher = Sha256::new();
+    hasher.update([BUCKET_TAG]);
+    let mut empty = true;
+    for (key, leaf) in entries {
+        hasher.update((key.len() as u32).to_be_bytes());
+        hasher.update(key);
+        hasher.update(leaf);
+        empty = false;
+    }
+    if empty {
+        return EMPTY_HASH;
+    }
+    hasher.finalize().into()
+}
+
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    if left == &EMPTY_HASH && right == &EMPTY_HASH {
+        return EMPTY_HASH;
+    }
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_TAG]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
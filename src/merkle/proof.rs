@@ -0,0 +1,134 @@
+use super::{bucket_hash, bucket_of, leaf_hash, node_hash, Hash, BUCKET_BITS};
+use crate::svm::{object::SVMObject, primitive_types::SVMPrimitives};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// One leaf of the proven key's bucket.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProofEntry {
+    #[serde(with = "hex::serde")]
+    pub key: Vec<u8>,
+    #[serde(with = "hex::serde")]
+    pub leaf: Hash,
+}
+
+/// Proof that a key holds a given object, or no object at all, under a
+/// state root. Bytes are hex encoded in JSON.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StateProof {
+    pub bucket: usize,
+    /// every leaf in the key's bucket, in key order
+    pub entries: Vec<ProofEntry>,
+    /// hashes of the sibling nodes from the bucket up to the root
+    #[serde(with = "hex_hashes")]
+    pub siblings: Vec<Hash>,
+}
+
+/// Checks that `proof` ties `key` to `object` under `root`. With `object`
+/// set this is an inclusion proof, with `None` it proves the key is absent.
+/// Needs nothing but the proof, so light clients can run it on their own.
+pub fn verify_proof(
+    root: &Hash,
+    key: &[u8],
+    object: Option<&SVMObject<SVMPrimitives>>,
+    proof: &StateProof,
+) -> Result<(), String> {
+    if proof.bucket != bucket_of(key) {
+        return Err(format!("proof is for bucket={}", proof.bucket));
+    }
+    if proof.siblings.len() != BUCKET_BITS as usize {
+        return Err(format!("proof has {} siblings", proof.siblings.len()));
+    }
+    if proof
+        .entries
+        .windows(2)
+        .any(|pair| pair[0].key >= pair[1].key)
+    {
+        return Err("proof entries are not in key order".to_string());
+    }
+
+    let entry = proof.entries.iter().find(|entry| entry.key == key);
+    match (object, entry) {
+        (Some(object), Some(entry)) => {
+            if entry.leaf != leaf_hash(key, object.version, &object.value) {
+                return Err("leaf does not match the object".to_string());
+            }
+        }
+        (Some(_), None) => return Err("key is missing from the proof".to_string()),
+        (None, Some(_)) => return Err("key is present in the proof".to_string()),
+        (None, None) => {}
+    }
+
+    let mut hash = bucket_hash(proof.entries.iter().map(|entry| (&entry.key, &entry.leaf)));
+    let mut index = (1 << BUCKET_BITS) + proof.bucket;
+    for sibling in &proof.siblings {
+        hash = if index & 1 == 0 {
+            node_hash(&hash, sibling)
+        } else {
+            node_hash(sibling, &hash)
+        };
+        index /= 2;
+    }
+    if &hash != root {
+        return Err(format!("proof leads to root={}", hex::encode(hash)));
+    }
+    Ok(())
+}
+
+mod hex_hashes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(hashes: &[Hash], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(hashes.iter().map(hex::encode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Hash>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|hash| hex::FromHex::from_hex(hash).map_err(serde::de::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::tree::StateTree;
+    use crate::storage::StoredObject;
+
+    #[test]
+    fn proofs_verify_against_root_only() {
+        let objects: Vec<StoredObject> = (0..100_000u32)
+            .map(|i| {
+                let value = SVMPrimitives::U24(i);
+                (
+                    format!("0x{}", i).into_bytes(),
+                    SVMObject { value, version: 1 },
+                )
+            })
+            .collect();
+        let mut tree = StateTree::from_objects(&objects);
+        let root = tree.root();
+
+        let (key, object) = &objects[4321];
+        let proof = tree.prove(key);
+        assert!(!proof.entries.is_empty());
+        assert_eq!(verify_proof(&root, key, Some(object), &proof), Ok(()));
+        assert!(verify_proof(&root, key, None, &proof).is_err());
+        let forged = SVMObject {
+            value: SVMPrimitives::U24(1_000_000),
+            version: 1,
+        };
+        assert!(verify_proof(&root, key, Some(&forged), &proof).is_err());
+        assert!(verify_proof(&root, b"0x1", Some(object), &proof).is_err());
+
+        // proofs survive a JSON round trip, as sent to clients
+        let json = serde_json::to_string(&proof).unwrap();
+        let decoded: StateProof = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, proof);
+
+        let missing = b"0x100000".to_vec();
+        let proof = tree.prove(&missing);
+        assert_eq!(verify_proof(&root, &missing, None, &proof), Ok(()));
+        assert!(verify_proof(&[1; 32], &missing, None, &proof).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use super::{
+    bucket_hash, bucket_of, leaf_hash, node_hash,
+    proof::{ProofEntry, StateProof},
+    Hash, BUCKET_BITS, EMPTY_HASH,
+};
+use crate::storage::StoredObject;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Merkle tree over the latest version of every object.
+///
+/// Keys are spread over `1 << BUCKET_BITS` buckets by their hash. A bucket
+/// hashes its leaves in key order and the buckets are the leaves of a
+/// complete binary tree. Empty subtrees hash to [`EMPTY_HASH`] and are not
+/// stored, so the root only depends on the objects, never on the order
+/// they were written in.
+///
+/// Setting leaves only marks their buckets dirty, the paths above them are
+/// rehashed once the root or a proof is asked for.
+#[derive(Default)]
+pub struct StateTree {
+    /// bucket -> key -> leaf
+    buckets: HashMap<usize, BTreeMap<Vec<u8>, Leaf>>,
+    /// buckets whose leaves changed since the nodes were last hashed
+    dirty: BTreeSet<usize>,
+    /// hashes of the non-empty nodes, numbered from the root at 1, the
+    /// children of `i` being `2i` and `2i + 1`
+    nodes: HashMap<usize, Hash>,
+}
+
+#[derive(Clone, Copy)]
+struct Leaf {
+    /// commit the leaf was written at, an older one never replaces it
+    seq: u64,
+    hash: Hash,
+}
+
+impl StateTree {
+    pub fn from_objects<'a, I>(objects: I) -> Self
+    where
+        I: IntoIterator<Item = &'a StoredObject>,
+    {
+        let mut tree = Self::default();
+        tree.update(objects);
+        tree
+    }
+
+    pub fn root(&mut self) -> Hash {
+        self.rehash();
+        self.node(1)
+    }
+
+    /// Sets the leaves of `objects`, the last one written to a key winning.
+    pub fn update<'a, I>(&mut self, objects: I)
+    where
+        I: IntoIterator<Item = &'a StoredObject>,
+    {
+        self.set_leaves(
+            0,
+            objects
+                .into_iter()
+                .map(|(key, object)| (key.clone(), leaf_hash(key, object.version, &object.value))),
+        );
+    }
+
+    /// Sets the leaf hashes written by commit `seq`. A key keeps its leaf if a
+    /// later commit already set it, so commits may be set in any order.
+    pub fn set_leaves<I>(&mut self, seq: u64, leaves: I)
+    where
+        I: IntoIterator<Item = (Vec<u8>, Hash)>,
+    {
+        for (key, hash) in leaves {
+            let bucket = bucket_of(&key);
+            let leaf = self
+                .buckets
+                .entry(bucket)
+                .or_default()
+                .entry(key)
+                .or_insert(Leaf { seq, hash });
+            if leaf.seq <= seq {
+                *leaf = Leaf { seq, hash };
+                self.dirty.insert(bucket);
+            }
+        }
+    }
+
+    /// Leaf hash `key` holds, if any.
+    pub fn leaf(&self, key: &[u8]) -> Option<Hash> {
+        self.buckets
+            .get(&bucket_of(key))
+            .and_then(|bucket| bucket.get(key))
+            .map(|leaf| leaf.hash)
+    }
+
+    /// Rehashes the paths above the dirty buckets.
+    fn rehash(&mut self) {
+        let dirty = std::mem::take(&mut self.dirty);
+        let mut level: BTreeSet<usize> = dirty
+            .into_iter()
+            .map(|bucket| {
+                let index = (1 << BUCKET_BITS) + bucket;
+                let hash = bucket_hash(
+                    self.buckets[&bucket]
+                        .iter()
+                        .map(|(key, leaf)| (key, &leaf.hash)),
+                );
+                self.set_node(index, hash);
+                index / 2
+            })
+            .collect();
+        while !level.is_empty() {
+            let mut parents = BTreeSet::new();
+            for index in level {
+                self.set_node(
+                    index,
+                    node_hash(&self.node(2 * index), &self.node(2 * index + 1)),
+                );
+                if index > 1 {
+                    parents.insert(index / 2);
+                }
+            }
+            level = parents;
+        }
+    }
+
+    /// Proof of what `key` holds under the current root, see
+    /// [`super::proof::verify_proof`].
+    pub fn prove(&mut self, key: &[u8]) -> StateProof {
+        self.rehash();
+        let bucket = bucket_of(key);
+        let entries = self
+            .buckets
+            .get(&bucket)
+            .into_iter()
+            .flatten()
+            .map(|(key, leaf)| ProofEntry {
+                key: key.clone(),
+                leaf: leaf.hash,
+            })
+            .collect();
+
+        let mut siblings = Vec::with_capacity(BUCKET_BITS as usize);
+        let mut index = (1 << BUCKET_BITS) + bucket;
+        while index > 1 {
+            siblings.push(self.node(index ^ 1));
+            index /= 2;
+        }
+        StateProof {
+            bucket,
+            entries,
+            siblings,
+        }
+    }
+
+    fn node(&self, index: usize) -> Hash {
+        self.nodes.get(&index).copied().unwrap_or(EMPTY_HASH)
+    }
+
+    fn set_node(&mut self, index: usize, hash: Hash) {
+        if hash == EMPTY_HASH {
+            self.nodes.remove(&index);
+        } else {
+            self.nodes.insert(index, hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::svm::{object::SVMObject, primitive_types::SVMPrimitives};
+
+    fn object(i: u32, version: u64) -> StoredObject {
+        let value = SVMPrimitives::Tup(vec![SVMPrimitives::U24(i), SVMPrimitives::Era]);
+        (
+            format!("0x{}", i).into_bytes(),
+            SVMObject { value, version },
+        )
+    }
+
+    #[test]
+    fn root_does_not_depend_on_write_order() {
+        assert_eq!(StateTree::default().root(), EMPTY_HASH);
+
+        let objects: Vec<StoredObject> = (0..1_000).map(|i| object(i, 1)).collect();
+        let mut forward = StateTree::from_objects(&objects);
+        let mut backward = StateTree::default();
+        for object in objects.iter().rev() {
+            backward.update([object]);
+        }
+        assert_ne!(forward.root(), EMPTY_HASH);
+        assert_eq!(forward.root(), backward.root());
+
+        // overwriting a leaf with the same object changes nothing, a new version does
+        backward.update([&object(7, 1)]);
+        assert_eq!(forward.root(), backward.root());
+        backward.update([&object(7, 2)]);
+        assert_ne!(forward.root(), backward.root());
+
+        // commits set out of order keep the leaf of the later one
+        let leaf = |object: &StoredObject| {
+            let (key, object) = object;
+            (key.clone(), leaf_hash(key, object.version, &object.value))
+        };
+        forward.set_leaves(9, [leaf(&object(7, 2))]);
+        forward.set_leaves(8, [leaf(&object(7, 3))]);
+        assert_eq!(forward.root(), backward.root());
+    }
+}
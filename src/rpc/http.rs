@@ -0,0 +1,174 @@
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Largest request line and headers accepted.
+const MAX_HEAD_BYTES: usize = 16 << 10;
+const MAX_HEADERS: usize = 32;
+
+/// The parts of an HTTP/1.1 request the JSON-RPC server looks at.
+#[derive(Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    /// whether the client wants to send more requests on the connection
+    pub keep_alive: bool,
+    /// token of an `Authorization: Bearer` header
+    pub bearer: Option<String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    /// a request the server cannot read, answered with `status` before
+    /// closing the connection
+    Rejected(u16, String),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+/// Reads the next request off `reader`, or `None` once the client closed the
+/// connection between requests. `buf` holds bytes read past the request, it
+/// is passed again to read the next one. Bodies over `max_body` bytes are
+/// rejected.
+pub async fn read_request<R>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    max_body: usize,
+) -> Result<Option<HttpRequest>, ReadError>
+where
+    R: AsyncRead + Unpin,
+{
+    let (head_len, method, path, keep_alive, bearer, content_length) = loop {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut request = httparse::Request::new(&mut headers);
+        match request.parse(buf) {
+            Ok(httparse::Status::Complete(head_len)) => {
+                let mut keep_alive = request.version == Some(1);
+                let mut bearer = None;
+                let mut content_length = 0;
+                for header in request.headers.iter() {
+                    let value = String::from_utf8_lossy(header.value);
+                    if header.name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().map_err(|_| {
+                            ReadError::Rejected(400, format!("invalid content-length={}", value))
+                        })?;
+                    } else if header.name.eq_ignore_ascii_case("transfer-encoding") {
+                        return Err(ReadError::Rejected(
+                            411,
+                            "send the body with a content-length".to_string(),
+                        ));
+                    } else if header.name.eq_ignore_ascii_case("authorization") {
+                        bearer = value
+                            .trim()
+                            .strip_prefix("Bearer ")
+                            .map(|token| token.trim().to_string());
+                    } else if header.name.eq_ignore_ascii_case("connection") {
+                        keep_alive = match value.trim().to_ascii_lowercase().as_str() {
+                            "close" => false,
+                            "keep-alive" => true,
+                            _ => keep_alive,
+                        };
+                    }
+                }
+                if content_length > max_body {
+                    return Err(ReadError::Rejected(
+                        413,
+                        format!("body of {} bytes is over {}", content_length, max_body),
+                    ));
+                }
+                let method = request.method.unwrap_or_default().to_string();
+                let path = request.path.unwrap_or_default().to_string();
+                break (head_len, method, path, keep_alive, bearer, content_length);
+            }
+            Ok(httparse::Status::Partial) if buf.len() > MAX_HEAD_BYTES => {
+                return Err(ReadError::Rejected(
+                    431,
+                    "request headers are too large".to_string(),
+                ));
+            }
+            Ok(httparse::Status::Partial) => {
+                if read_more(reader, buf).await? == 0 {
+                    if buf.is_empty() {
+                        return Ok(None);
+                    }
+                    return Err(ReadError::Io(io::ErrorKind::UnexpectedEof.into()));
+                }
+            }
+            Err(e) => return Err(ReadError::Rejected(400, e.to_string())),
+        }
+    };
+
+    while buf.len() < head_len + content_length {
+        if read_more(reader, buf).await? == 0 {
+            return Err(ReadError::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+    }
+    let body = buf[head_len..head_len + content_length].to_vec();
+    buf.drain(..head_len + content_length);
+    Ok(Some(HttpRequest {
+        method,
+        path,
+        keep_alive,
+        bearer,
+        body,
+    }))
+}
+
+async fn read_more<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let mut chunk = [0u8; 8192];
+    let read = reader.read(&mut chunk).await?;
+    buf.extend_from_slice(&chunk[..read]);
+    Ok(read)
+}
+
+/// Writes a response with a JSON `body`, which is left out for 204.
+pub async fn write_response<W>(
+    writer: &mut W,
+    status: u16,
+    body: &str,
+    keep_alive: bool,
+) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let connection = if keep_alive { "keep-alive" } else { "close" };
+    let head = match status {
+        204 => format!(
+            "HTTP/1.1 204 {}\r\nConnection: {}\r\n\r\n",
+            reason(status),
+            connection
+        ),
+        _ => format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+            status,
+            reason(status),
+            body.len(),
+            connection
+        ),
+    };
+    writer.write_all(head.as_bytes()).await?;
+    if status != 204 {
+        writer.write_all(body.as_bytes()).await?;
+    }
+    writer.flush().await
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
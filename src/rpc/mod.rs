@@ -0,0 +1,548 @@
+use crate::ws::{
+    auth::unauthorized,
+    events::{ApiError, ErrorCode, Message},
+    handler::handle,
+    limits::ConnectionLimits,
+    Node,
+};
+use futures::future::join_all;
+use http::{read_request, write_response, ReadError};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+    time::timeout,
+};
+
+pub mod http;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// server error: what the request names does not exist
+pub const NOT_FOUND: i64 = -32001;
+/// server error: an admin method without the admin token
+pub const UNAUTHORIZED: i64 = -32003;
+/// server error: the node has no room for the request, retry later
+pub const BUSY: i64 = -32005;
+
+/// JSON-RPC methods and the messages they send. `params` are the fields of
+/// the message, the same as the `body` of a WebSocket request.
+pub const METHODS: &[(&str, &str)] = &[
+    ("submit", "SubmitTx"),
+    ("query", "GetValueAt"),
+    ("simulate", "Simulate"),
+    ("deploy", "DeployCode"),
+    ("status", "GetTxStatus"),
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+    /// `id` of the request, null if it could not be read
+    pub id: Value,
+}
+
+impl RpcResponse {
+    fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            result,
+            error,
+            id,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    /// `{"code": ..}` with the `ErrorCode` of errors from the node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+impl From<ApiError> for RpcError {
+    fn from(e: ApiError) -> Self {
+        let code = match e.code {
+            ErrorCode::InvalidRequest | ErrorCode::InvalidArgument => INVALID_PARAMS,
+            ErrorCode::UnsupportedVersion => INVALID_REQUEST,
+            ErrorCode::NotFound => NOT_FOUND,
+            ErrorCode::Internal => INTERNAL_ERROR,
+            ErrorCode::Busy => BUSY,
+            ErrorCode::Unauthorized => UNAUTHORIZED,
+        };
+        Self {
+            code,
+            message: e.message,
+            data: Some(json!({ "code": e.code })),
+        }
+    }
+}
+
+pub async fn run_rpc(addr: &str, node: Arc<Node>) {
+    let listener = TcpListener::bind(&addr).await.expect("Failed to bind");
+    info!("json-rpc is running on: {}", addr);
+    serve(listener, node).await;
+}
+
+/// Serves JSON-RPC over HTTP on `listener`, from the same node as the
+/// WebSocket server. Connections over the limit are answered `503` and
+/// closed. The connections of one peer share the limits of a connection.
+pub async fn serve(listener: TcpListener, node: Arc<Node>) {
+    let config = node.limits.config();
+    let connections = Arc::new(Semaphore::new(config.rpc_connections.max(1)));
+    let idle_timeout = config.rpc_idle_timeout;
+    while let Ok((mut stream, peer)) = listener.accept().await {
+        match connections.clone().try_acquire_owned() {
+            Ok(permit) => {
+                let node = node.clone();
+                tokio::spawn(async move {
+                    handle_connection(stream, node, peer).await;
+                    drop(permit);
+                });
+            }
+            Err(_) => {
+                debug!("refused rpc connection peer={}, too many connections", peer);
+                tokio::spawn(async move {
+                    let body = json!({ "error": "too many connections" }).to_string();
+                    let _ =
+                        timeout(idle_timeout, write_response(&mut stream, 503, &body, false)).await;
+                });
+            }
+        }
+    }
+}
+
+/// Answers requests one after another until the client closes the
+/// connection, asks to, or takes longer than the idle timeout to send the
+/// next request or read the answer.
+async fn handle_connection(mut stream: TcpStream, node: Arc<Node>, peer: SocketAddr) {
+    let limits = node.limits.peer(peer.ip());
+    let mut buf = vec![];
+    let config = node.limits.config();
+    let (max_body, idle_timeout) = (config.max_message_bytes, config.rpc_idle_timeout);
+    loop {
+        let read = timeout(idle_timeout, read_request(&mut stream, &mut buf, max_body));
+        let request = match read.await {
+            Ok(Ok(Some(request))) => request,
+            Ok(Ok(None)) => return,
+            Ok(Err(ReadError::Io(e))) => {
+                debug!("rpc connection failed: {}", e);
+                return;
+            }
+            Ok(Err(ReadError::Rejected(status, message))) => {
+                let body = json!({ "error": message }).to_string();
+                let _ = timeout(
+                    idle_timeout,
+                    write_response(&mut stream, status, &body, false),
+                )
+                .await;
+                return;
+            }
+            Err(_) => {
+                debug!("closing idle rpc connection peer={}", peer);
+                return;
+            }
+        };
+
+        let (status, body) = if request.method != "POST" {
+            let body = json!({ "error": "send JSON-RPC requests with POST" });
+            (405, body.to_string())
+        } else if request.path != "/" {
+            let body = json!({ "error": format!("no such path={}", request.path) });
+            (404, body.to_string())
+        } else {
+            let caller = Caller {
+                peer,
+                admin: (request.bearer.as_deref()).is_some_and(|token| node.admin.check(token)),
+                limits: limits.clone(),
+            };
+            match answer(&node, &caller, &request.body).await {
+                Some(response) => (200, response.to_string()),
+                None => (204, String::new()),
+            }
+        };
+        let written = timeout(
+            idle_timeout,
+            write_response(&mut stream, status, &body, request.keep_alive),
+        );
+        match written.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                error!("failed to send rpc reply: {}", e);
+                return;
+            }
+            Err(_) => {
+                debug!("rpc client peer={} does not read its reply", peer);
+                return;
+            }
+        }
+        if !request.keep_alive {
+            return;
+        }
+    }
+}
+
+/// Who sent an HTTP request.
+pub struct Caller {
+    pub peer: SocketAddr,
+    /// whether it carries the admin token as its bearer token
+    pub admin: bool,
+    /// limits of the peer, shared by its connections
+    pub limits: Arc<ConnectionLimits>,
+}
+
+/// Answers the request or batch of requests in `body`. `None` if there is
+/// nothing to answer, i.e. only notifications.
+pub async fn answer(node: &Node, caller: &Caller, body: &[u8]) -> Option<Value> {
+    let request = match serde_json::from_slice::<Value>(body) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = RpcError::new(PARSE_ERROR, e.to_string());
+            return Some(json!(RpcResponse::new(Value::Null, Err(error))));
+        }
+    };
+    match request {
+        Value::Array(requests) if requests.is_empty() => {
+            let error = RpcError::new(INVALID_REQUEST, "empty batch");
+            Some(json!(RpcResponse::new(Value::Null, Err(error))))
+        }
+        // answered in the order sent, though they run concurrently
+        Value::Array(requests) => {
+            let responses: Vec<RpcResponse> = join_all(
+                requests
+                    .into_iter()
+                    .map(|request| call(node, caller, request)),
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+            (!responses.is_empty()).then(|| json!(responses))
+        }
+        request => call(node, caller, request)
+            .await
+            .map(|response| json!(response)),
+    }
+}
+
+/// Runs one request, `None` for a notification.
+async fn call(node: &Node, caller: &Caller, request: Value) -> Option<RpcResponse> {
+    let id = match request.get("id") {
+        None => None,
+        Some(id @ (Value::Null | Value::String(_) | Value::Number(_))) => Some(id.clone()),
+        Some(_) => {
+            let error = RpcError::new(INVALID_REQUEST, "id must be a string, number or null");
+            return Some(RpcResponse::new(Value::Null, Err(error)));
+        }
+    };
+    let result = match message(&request) {
+        Ok(message) if message.is_admin() && !caller.admin => {
+            warn!(
+                "refused unauthenticated admin message type={} peer={}",
+                message.kind(),
+                caller.peer
+            );
+            Err(unauthorized(message.kind()).into())
+        }
+        // every call of a batch counts against the peer's and node's limits
+        Ok(message) => match caller.limits.admit(1) {
+            Ok(_admitted) => {
+                info!("Received rpc id={:?} {:?}", id, message);
+                handle(node, message).await.map_err(RpcError::from)
+            }
+            Err(e) => Err(e.into()),
+        },
+        Err(e) => Err(e),
+    };
+    // a notification gets no answer, even for an invalid request
+    id.map(|id| RpcResponse::new(id, result))
+}
+
+/// The message a request sends.
+fn message(request: &Value) -> Result<Message, RpcError> {
+    if request.get("jsonrpc").and_then(Value::as_str) != Some(JSONRPC_VERSION) {
+        return Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""));
+    }
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        return Err(RpcError::new(INVALID_REQUEST, "method must be a string"));
+    };
+    let Some((_, kind)) = METHODS.iter().find(|(name, _)| *name == method) else {
+        return Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("no such method={}", method),
+        ));
+    };
+    let params = match request.get("params") {
+        None | Some(Value::Null) => json!({}),
+        Some(params @ Value::Object(_)) => params.clone(),
+        Some(_) => {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                "params must be an object with the fields of the method",
+            ))
+        }
+    };
+    serde_json::from_value(json!({ *kind: params })).map_err(|e| {
+        RpcError::new(
+            INVALID_PARAMS,
+            format!("invalid params of {} err={}", method, e),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_stm::svm_memory::SVMMemory;
+    use crate::executor::engine::EngineConfig;
+    use crate::svm::{codes::load_codes, object::SVMObject, primitive_types::SVMPrimitives};
+    use crate::test_support::test_node;
+    use crate::ws::limits::{LimitConfig, Limits};
+    use std::path::Path;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn start(data_dir: &Path, limits: LimitConfig) -> String {
+        let tm = Arc::new(SVMMemory::new());
+        tm.set(
+            b"0x1".to_vec(),
+            SVMObject {
+                value: SVMPrimitives::U24(5),
+                version: 1,
+            },
+        )
+        .unwrap();
+        let engine = EngineConfig {
+            workers: 2,
+            ..EngineConfig::default()
+        };
+        let node = Node {
+            data_dir: Some(data_dir.to_path_buf()),
+            limits: Arc::new(Limits::new(limits)),
+            ..test_node(tm, engine, None)
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(serve(listener, Arc::new(node)));
+        addr
+    }
+
+    /// Sends one request on `stream` and reads the status and body of the reply.
+    async fn send(stream: &mut TcpStream, method: &str, body: &str) -> (u16, Value) {
+        send_with(stream, method, "", body).await
+    }
+
+    /// Like `send`, with more header lines.
+    async fn send_with(
+        stream: &mut TcpStream,
+        method: &str,
+        headers: &str,
+        body: &str,
+    ) -> (u16, Value) {
+        let request = format!(
+            "{} / HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: {}\r\n\r\n{}",
+            method,
+            headers,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut buf = vec![];
+        loop {
+            let mut chunk = [0u8; 4096];
+            let read = stream.read(&mut chunk).await.unwrap();
+            assert!(read > 0, "connection closed");
+            buf.extend_from_slice(&chunk[..read]);
+
+            let mut headers = [httparse::EMPTY_HEADER; 8];
+            let mut response = httparse::Response::new(&mut headers);
+            let httparse::Status::Complete(head_len) = response.parse(&buf).unwrap() else {
+                continue;
+            };
+            let content_length: usize = response
+                .headers
+                .iter()
+                .find(|header| header.name.eq_ignore_ascii_case("content-length"))
+                .map_or(0, |header| {
+                    std::str::from_utf8(header.value).unwrap().parse().unwrap()
+                });
+            if buf.len() < head_len + content_length {
+                continue;
+            }
+            let status = response.code.unwrap();
+            let body = &buf[head_len..head_len + content_length];
+            let body = if body.is_empty() {
+                Value::Null
+            } else {
+                serde_json::from_slice(body).unwrap()
+            };
+            return (status, body);
+        }
+    }
+
+    fn error_code(response: &Value) -> i64 {
+        response["error"]["code"].as_i64().unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_json_rpc_over_http() {
+        let dir = tempfile::tempdir().unwrap();
+        let addr = start(dir.path(), LimitConfig::default()).await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+        // several requests on one connection
+        let (status, response) = send(
+            &mut stream,
+            "POST",
+            r#"{"jsonrpc":"2.0","id":1,"method":"query","params":{"addr":"0x1"}}"#,
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            response,
+            json!({ "jsonrpc": "2.0", "id": 1, "result": { "addr": "0x1", "value": { "U24": 5 } } })
+        );
+        let (_, response) = send(
+            &mut stream,
+            "POST",
+            r#"{"jsonrpc":"2.0","id":"a","method":"status","params":{"tx_hash":"0xnone"}}"#,
+        )
+        .await;
+        assert_eq!(response["id"], "a");
+        assert_eq!(response["result"]["status"], "unknown");
+
+        let (_, responses) = send(
+            &mut stream,
+            "POST",
+            r#"[
+                {"jsonrpc":"2.0","id":1,"method":"query","params":{"addr":"0x2"}},
+                {"jsonrpc":"2.0","method":"query","params":{"addr":"0x1"}},
+                {"jsonrpc":"2.0","id":2,"method":"drop"},
+                {"jsonrpc":"2.0","id":3,"method":"query","params":["0x1"]},
+                {"jsonrpc":"2.0","id":4,"method":"simulate","params":{"tx_body":{
+                    "tx_hash":"0xtx","code_hash":"0xnone","objs":[],"args":[]}}}
+            ]"#,
+        )
+        .await;
+        let responses = responses.as_array().unwrap();
+        let ids: Vec<&Value> = responses.iter().map(|response| &response["id"]).collect();
+        assert_eq!(ids, vec![&json!(1), &json!(2), &json!(3), &json!(4)]);
+        assert_eq!(responses[0]["result"]["value"], Value::Null);
+        assert_eq!(error_code(&responses[1]), METHOD_NOT_FOUND);
+        assert_eq!(error_code(&responses[2]), INVALID_PARAMS);
+        assert_eq!(error_code(&responses[3]), NOT_FOUND);
+        assert_eq!(
+            responses[3]["error"]["data"],
+            json!({ "code": "not_found" })
+        );
+
+        let deploy = r#"{"jsonrpc":"2.0","id":5,"method":"deploy",
+            "params":{"code_id":"0xecho","source":"def main(a):\n  return a\n"}}"#;
+        let (_, response) = send(&mut stream, "POST", deploy).await;
+        assert_eq!(error_code(&response), UNAUTHORIZED);
+        let wrong = "Authorization: Bearer guess\r\n";
+        let (_, response) = send_with(&mut stream, "POST", wrong, deploy).await;
+        assert_eq!(error_code(&response), UNAUTHORIZED);
+        let admin = "Authorization: Bearer s3cret\r\n";
+        let (_, response) = send_with(&mut stream, "POST", admin, deploy).await;
+        assert_eq!(response["result"], json!({ "code_id": "0xecho" }));
+        let (_, response) = send_with(&mut stream, "POST", admin, deploy).await;
+        assert_eq!(error_code(&response), INVALID_PARAMS);
+        let codes = load_codes(dir.path()).unwrap();
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].0, "0xecho");
+
+        let (_, response) = send(&mut stream, "POST", "{").await;
+        assert_eq!(error_code(&response), PARSE_ERROR);
+        assert_eq!(response["id"], Value::Null);
+        let (status, _) = send(
+            &mut stream,
+            "POST",
+            r#"{"jsonrpc":"2.0","method":"query","params":{"addr":"0x1"}}"#,
+        )
+        .await;
+        assert_eq!(status, 204);
+        let (status, _) = send(&mut stream, "GET", "").await;
+        assert_eq!(status, 405);
+    }
+
+    #[tokio::test]
+    async fn idle_and_extra_connections_are_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        let limits = LimitConfig {
+            rpc_connections: 1,
+            rpc_idle_timeout: Duration::from_millis(200),
+            ..LimitConfig::default()
+        };
+        let addr = start(dir.path(), limits).await;
+        let query = r#"{"jsonrpc":"2.0","id":1,"method":"query","params":{"addr":"0x1"}}"#;
+
+        let mut idle = TcpStream::connect(&addr).await.unwrap();
+        let (status, _) = send(&mut idle, "POST", query).await;
+        assert_eq!(status, 200);
+        // turned away before it sends anything
+        let mut extra = TcpStream::connect(&addr).await.unwrap();
+        let mut reply = vec![];
+        extra.read_to_end(&mut reply).await.unwrap();
+        assert!(reply.starts_with(b"HTTP/1.1 503 Service Unavailable\r\n"));
+
+        // half a request, then nothing
+        idle.write_all(b"POST / HTTP/1.1\r\n").await.unwrap();
+        let mut rest = vec![];
+        let read = timeout(Duration::from_secs(5), idle.read_to_end(&mut rest));
+        assert_eq!(read.await.unwrap().unwrap(), 0);
+
+        let mut next = TcpStream::connect(&addr).await.unwrap();
+        let (status, _) = send(&mut next, "POST", query).await;
+        assert_eq!(status, 200);
+    }
+
+    #[tokio::test]
+    async fn connections_of_a_peer_share_its_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let limits = LimitConfig {
+            connection_rate: 2,
+            ..LimitConfig::default()
+        };
+        let addr = start(dir.path(), limits).await;
+        let query = r#"{"jsonrpc":"2.0","id":1,"method":"query","params":{"addr":"0x1"}}"#;
+
+        let mut first = TcpStream::connect(&addr).await.unwrap();
+        let mut second = TcpStream::connect(&addr).await.unwrap();
+        let (_, response) = send(&mut first, "POST", query).await;
+        assert!(response.get("result").is_some());
+        let (_, response) = send(&mut second, "POST", query).await;
+        assert!(response.get("result").is_some());
+        let (_, response) = send(&mut first, "POST", query).await;
+        assert_eq!(error_code(&response), BUSY);
+    }
+}
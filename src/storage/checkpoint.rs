@@ -0,0 +1,212 @@
+use super::export::{read_snapshot, write_snapshot, SnapshotHeader};
+use crate::block_stm::svm_memory::{CommitSeq, SVMMemory};
+use log::{error, info};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+pub const CHECKPOINTS_DIR: &str = "checkpoints";
+
+#[derive(Clone, Debug)]
+pub struct CheckpointConfig {
+    /// time between checkpoints, zero turns them off
+    pub interval: Duration,
+    /// checkpoint files kept, the oldest ones are deleted
+    pub keep: usize,
+}
+
+impl Default for CheckpointConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(600),
+            keep: 2,
+        }
+    }
+}
+
+/// Checkpoint files in the data dir `dir` with the commit seqs they were
+/// taken at, oldest first.
+pub fn list_checkpoints(dir: &Path) -> io::Result<Vec<(CommitSeq, PathBuf)>> {
+    let dir = dir.join(CHECKPOINTS_DIR);
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut checkpoints = vec![];
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "snapshot") {
+            continue;
+        }
+        if let Some(seq) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<CommitSeq>().ok())
+        {
+            checkpoints.push((seq, path));
+        }
+    }
+    checkpoints.sort();
+    Ok(checkpoints)
+}
+
+/// Writes the latest state to the data dir `dir` as a checkpoint named by
+/// the commit seq it was read at, then drops the history it covers: the
+/// storage is compacted to the checkpoint, log segments before it and
+/// checkpoints past `keep` are deleted and old object versions are collected.
+pub fn checkpoint(tm: &SVMMemory, dir: &Path, keep: usize) -> io::Result<SnapshotHeader> {
+    let checkpoints_dir = dir.join(CHECKPOINTS_DIR);
+    fs::create_dir_all(&checkpoints_dir)?;
+    let snapshot = tm.snapshot();
+    let path = checkpoints_dir.join(format!("{:012}.snapshot", snapshot.seq()));
+    write_snapshot(&snapshot, &path)?;
+    drop(snapshot);
+
+    // read back, so the storage is only replaced by a checkpoint that verifies
+    let (header, objects) = read_snapshot(&path)?;
+    tm.compact_storage(&objects)?;
+    if let Some(wal) = tm.wal() {
+        wal.prune(header.seq)?;
+    }
+
+    let checkpoints = list_checkpoints(dir)?;
+    let stale = checkpoints.len().saturating_sub(keep.max(1));
+    for (_, path) in &checkpoints[..stale] {
+        fs::remove_file(path)?;
+    }
+    tm.collect_garbage();
+    Ok(header)
+}
+
+/// Periodically checkpoints the state in the data dir `dir`. Skipped while
+/// nothing is committed.
+pub async fn run_checkpoints(tm: Arc<SVMMemory>, dir: PathBuf, config: CheckpointConfig) {
+    if config.interval.is_zero() {
+        return;
+    }
+    let mut interval = tokio::time::interval(config.interval);
+    // the first tick completes right away
+    interval.tick().await;
+    let mut last_seq = None;
+    loop {
+        interval.tick().await;
+        if last_seq == Some(tm.latest_seq()) {
+            continue;
+        }
+        let (tm, dir) = (tm.clone(), dir.clone());
+        let written = tokio::task::spawn_blocking(move || checkpoint(&tm, &dir, config.keep)).await;
+        match written {
+            Ok(Ok(header)) => {
+                info!(
+                    "checkpointed seq={} objects={} state_root={}",
+                    header.seq, header.objects, header.state_root
+                );
+                last_seq = Some(header.seq);
+            }
+            Ok(Err(e)) => error!("failed to checkpoint err={}", e),
+            Err(e) => error!("failed to checkpoint err={}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_stm::svm_memory::retry_transaction;
+    use crate::storage::{
+        disk_usage,
+        file::{FileStorage, OBJECTS_FILE},
+        open_durable,
+        wal::Wal,
+        WAL_DIR,
+    };
+    use crate::svm::primitive_types::SVMPrimitives;
+    use std::ops::Range;
+
+    fn write(tm: &Arc<SVMMemory>, rounds: Range<u32>) {
+        for i in rounds {
+            retry_transaction(tm.clone(), |txn| {
+                txn.write(format!("0x{}", i % 10).into_bytes(), SVMPrimitives::U24(i));
+                Ok(SVMPrimitives::Era)
+            })
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn checkpoints_prune_history_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let (wal, _) = Wal::open(&dir.path().join(WAL_DIR), false).unwrap();
+        let tm = Arc::new(
+            SVMMemory::new()
+                .with_storage(Arc::new(FileStorage::open(dir.path(), false).unwrap()))
+                .with_wal(Arc::new(wal.with_segment_bytes(1024))),
+        );
+        write(&tm, 0..200);
+        assert!(tm.wal().unwrap().segments() > 1);
+        let before = disk_usage(dir.path()).unwrap();
+
+        let header = checkpoint(&tm, dir.path(), 2).unwrap();
+        assert_eq!(header.state_root, hex::encode(tm.state_root()));
+        assert_eq!(header.objects, 10);
+        assert_eq!(tm.wal().unwrap().segments(), 1);
+        let after = disk_usage(dir.path()).unwrap();
+        assert!(after.objects_bytes < before.objects_bytes);
+        assert!(after.wal_bytes < before.wal_bytes);
+        assert_eq!(after.checkpoints.len(), 1);
+
+        write(&tm, 200..250);
+        let second = checkpoint(&tm, dir.path(), 2).unwrap();
+        write(&tm, 250..251);
+        let third = checkpoint(&tm, dir.path(), 2).unwrap();
+        // named by the seq whose state they hold
+        let seqs: Vec<CommitSeq> = list_checkpoints(dir.path())
+            .unwrap()
+            .into_iter()
+            .map(|(seq, _)| seq)
+            .collect();
+        assert_eq!(seqs, vec![second.seq, third.seq]);
+        assert_eq!(third.seq, tm.latest_seq());
+
+        // only in the log, after the latest checkpoint
+        write(&tm, 251..260);
+        let root = tm.state_root();
+        drop(tm);
+
+        let restarted = open_durable(SVMMemory::new(), dir.path(), false).unwrap();
+        assert_eq!(restarted.state_root(), root);
+        drop(restarted);
+
+        fs::remove_file(dir.path().join(OBJECTS_FILE)).unwrap();
+        let restored = open_durable(SVMMemory::new(), dir.path(), false).unwrap();
+        assert_eq!(restored.state_root(), root);
+        assert_eq!(
+            restored.get(b"0x9".to_vec()).unwrap().value,
+            SVMPrimitives::U24(259)
+        );
+    }
+
+    #[test]
+    fn checkpoints_after_a_restart_come_after_the_older_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let tm = Arc::new(open_durable(SVMMemory::new(), dir.path(), false).unwrap());
+        write(&tm, 0..50);
+        let first = checkpoint(&tm, dir.path(), 1).unwrap();
+        drop(tm);
+
+        let tm = Arc::new(open_durable(SVMMemory::new(), dir.path(), false).unwrap());
+        assert!(tm.latest_seq() > first.seq);
+        write(&tm, 50..51);
+        let second = checkpoint(&tm, dir.path(), 1).unwrap();
+        assert!(second.seq > first.seq);
+        let checkpoints = list_checkpoints(dir.path()).unwrap();
+        assert_eq!(checkpoints.len(), 1);
+        assert_eq!(checkpoints[0].0, second.seq);
+        assert_eq!(
+            read_snapshot(&checkpoints[0].1).unwrap().0.state_root,
+            hex::encode(tm.state_root())
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use super::{
+    file::to_io_error,
+    record_log::{read_file, RecordWriter},
+    StoredObject,
+};
+use crate::block_stm::{
+    key_range::KeyRange,
+    svm_memory::{CommitSeq, SVMMemory, Snapshot},
+};
+use crate::merkle::tree::StateTree;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+/// Directory in the data dir that `ExportSnapshot` writes into, unless
+/// another one is configured.
+pub const SNAPSHOTS_DIR: &str = "snapshots";
+
+/// Objects per record, and per page read from the memory while exporting.
+const CHUNK_SIZE: usize = 4096;
+
+/// First record of a snapshot file. The objects follow in key order, in
+/// records of up to `CHUNK_SIZE` entries.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotHeader {
+    pub format_version: u32,
+    /// commit sequence the state was read at
+    pub seq: CommitSeq,
+    pub objects: u64,
+    /// hex merkle root over every object, checked on import
+    pub state_root: String,
+    /// unix time in milliseconds when the export started
+    pub timestamp: i64,
+}
+
+/// Visits every object of `snapshot` in key order, a page at a time.
+fn for_each_page<F>(snapshot: &Snapshot, mut f: F) -> io::Result<()>
+where
+    F: FnMut(&[StoredObject]) -> io::Result<()>,
+{
+    let mut range = KeyRange::all();
+    loop {
+        let page = snapshot.scan(&range, CHUNK_SIZE);
+        f(&page)?;
+        match page.last() {
+            Some((last, _)) if page.len() == CHUNK_SIZE => range = range.after(last),
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Writes the latest committed state to `path`. Reads go to a snapshot, so
+/// commits keep going while the export runs.
+pub fn export_snapshot(tm: &SVMMemory, path: &Path) -> io::Result<SnapshotHeader> {
+    write_snapshot(&tm.snapshot(), path)
+}
+
+/// Writes the state `snapshot` reads to `path`.
+pub fn write_snapshot(snapshot: &Snapshot, path: &Path) -> io::Result<SnapshotHeader> {
+    let timestamp = chrono::Utc::now().timestamp_millis();
+
+    let mut tree = StateTree::default();
+    let mut objects = 0u64;
+    for_each_page(snapshot, |page| {
+        tree.update(page);
+        objects += page.len() as u64;
+        Ok(())
+    })?;
+
+    let header = SnapshotHeader {
+        format_version: SNAPSHOT_FORMAT_VERSION,
+        seq: snapshot.seq(),
+        objects,
+        state_root: hex::encode(tree.root()),
+        timestamp,
+    };
+
+    let mut file = RecordWriter::create(path)?;
+    file.append(&bincode::serialize(&header).map_err(to_io_error)?)?;
+    for_each_page(snapshot, |page| {
+        if page.is_empty() {
+            return Ok(());
+        }
+        file.append(&bincode::serialize(page).map_err(to_io_error)?)
+    })?;
+    file.finish()?;
+
+    Ok(header)
+}
+
+/// Reads a snapshot file, checking its objects against the header's state root.
+pub fn read_snapshot(path: &Path) -> io::Result<(SnapshotHeader, Vec<StoredObject>)> {
+    let records = read_file(path)?;
+    let Some((header, chunks)) = records.split_first() else {
+        return Err(invalid_data("snapshot has no header".to_string()));
+    };
+    let header: SnapshotHeader = bincode::deserialize(header).map_err(to_io_error)?;
+    if header.format_version != SNAPSHOT_FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported snapshot format_version={}",
+            header.format_version
+        )));
+    }
+
+    let mut objects = Vec::with_capacity(header.objects as usize);
+    for chunk in chunks {
+        let chunk: Vec<StoredObject> = bincode::deserialize(chunk).map_err(to_io_error)?;
+        objects.extend(chunk);
+    }
+
+    let state_root = hex::encode(StateTree::from_objects(&objects).root());
+    if objects.len() as u64 != header.objects || state_root != header.state_root {
+        return Err(invalid_data(format!(
+            "snapshot does not match its header objects={} state_root={}",
+            objects.len(),
+            state_root
+        )));
+    }
+    Ok((header, objects))
+}
+
+/// Seeds an empty node with the state in the snapshot at `path`, in one
+/// commit so a failed import leaves nothing behind.
+pub fn import_snapshot(tm: &SVMMemory, path: &Path) -> io::Result<SnapshotHeader> {
+    if !tm.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "snapshots can only be imported into an empty node",
+        ));
+    }
+    let (header, objects) = read_snapshot(path)?;
+    tm.import(header.seq, &objects)?;
+    Ok(header)
+}
+
+/// The file `name` names inside the snapshot dir `dir`. Absolute names and
+/// names with `..` are refused, clients only write inside `dir`.
+pub fn snapshot_path(dir: &Path, name: &str) -> io::Result<PathBuf> {
+    let name = Path::new(name);
+    let inside = name
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if name.as_os_str().is_empty() || !inside {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "snapshot path={} must be relative to the snapshot dir, without ..",
+                name.display()
+            ),
+        ));
+    }
+    Ok(dir.join(name))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_stm::svm_memory::retry_transaction;
+    use crate::svm::primitive_types::SVMPrimitives;
+    use std::sync::Arc;
+
+    #[test]
+    fn export_then_import_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.snapshot");
+
+        let tm = Arc::new(SVMMemory::new());
+        for i in 0..5_000u32 {
+            retry_transaction(tm.clone(), |txn| {
+                txn.write(format!("0x{}", i).into_bytes(), SVMPrimitives::U24(i));
+                Ok(SVMPrimitives::Era)
+            })
+            .unwrap();
+        }
+        let header = export_snapshot(&tm, &path).unwrap();
+        assert_eq!(header.objects, 5_000);
+        assert_eq!(header.seq, tm.latest_seq());
+
+        let fresh = SVMMemory::new();
+        let imported = import_snapshot(&fresh, &path).unwrap();
+        assert_eq!(imported.state_root, header.state_root);
+        assert_eq!(header.state_root, hex::encode(tm.state_root()));
+        assert_eq!(fresh.state_root(), tm.state_root());
+        assert_eq!(fresh.len(), 5_000);
+        assert_eq!(
+            fresh.get(b"0x4321".to_vec()).unwrap().value,
+            SVMPrimitives::U24(4321)
+        );
+        assert!(fresh.latest_seq() > header.seq);
+        assert!(import_snapshot(&fresh, &path).is_err());
+
+        assert_eq!(
+            snapshot_path(dir.path(), "daily/1.snapshot").unwrap(),
+            dir.path().join("daily").join("1.snapshot")
+        );
+        for outside in ["", "/etc/passwd", "../state.snapshot", "daily/../../x"] {
+            assert!(snapshot_path(dir.path(), outside).is_err(), "{}", outside);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use super::{record_log::RecordLog, Storage, StoredObject};
+use log::info;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+pub const OBJECTS_FILE: &str = "objects.log";
+
+/// Embedded storage in a data directory: every commit's write set is appended
+/// to a log file as one checksummed record, so a batch is either fully there or
+/// not at all. The log is compacted to one entry per key when it is opened.
+pub struct FileStorage {
+    dir: PathBuf,
+    log: RecordLog,
+    /// fsync every batch instead of only handing it to the OS
+    sync: bool,
+    loaded: Vec<StoredObject>,
+}
+
+impl FileStorage {
+    pub fn open(dir: &Path, sync: bool) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let (log, records) = RecordLog::open(&dir.join(OBJECTS_FILE))?;
+
+        let mut latest: HashMap<Vec<u8>, _> = HashMap::new();
+        let mut entries = 0;
+        for record in &records {
+            let batch: Vec<StoredObject> = bincode::deserialize(record).map_err(to_io_error)?;
+            entries += batch.len();
+            for (key, object) in batch {
+                latest.insert(key, object);
+            }
+        }
+        let mut loaded: Vec<StoredObject> = latest.into_iter().collect();
+        loaded.sort_by(|a, b| a.0.cmp(&b.0));
+
+        // already compact after a checkpoint, when no key is in there twice
+        if entries > loaded.len() {
+            rewrite(&log, &loaded)?;
+        }
+        info!(
+            "opened file storage dir={} objects={} records={}",
+            dir.display(),
+            loaded.len(),
+            records.len()
+        );
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            log,
+            sync,
+            loaded,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Storage for FileStorage {
+    fn load(&self) -> io::Result<Vec<StoredObject>> {
+        Ok(self.loaded.clone())
+    }
+
+    fn write_batch(&self, objects: &[StoredObject]) -> io::Result<()> {
+        let record = bincode::serialize(objects).map_err(to_io_error)?;
+        self.log.append(&record, self.sync)
+    }
+
+    fn compact(&self, objects: &[StoredObject]) -> io::Result<()> {
+        rewrite(&self.log, objects)
+    }
+}
+
+fn rewrite(log: &RecordLog, objects: &[StoredObject]) -> io::Result<()> {
+    log.rewrite(
+        objects
+            .chunks(4096)
+            .map(|chunk| bincode::serialize(chunk).unwrap()),
+    )
+}
+
+pub(crate) fn to_io_error(e: bincode::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::svm::{object::SVMObject, primitive_types::SVMPrimitives};
+
+    fn object(value: u32, version: u64) -> SVMObject<SVMPrimitives> {
+        SVMObject {
+            value: SVMPrimitives::U24(value),
+            version,
+        }
+    }
+
+    #[test]
+    fn reopen_keeps_latest_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let storage = FileStorage::open(dir.path(), false).unwrap();
+            storage
+                .write_batch(&[(b"a".to_vec(), object(1, 1)), (b"b".to_vec(), object(2, 1))])
+                .unwrap();
+            storage
+                .write_batch(&[(b"a".to_vec(), object(3, 2))])
+                .unwrap();
+        }
+
+        let storage = FileStorage::open(dir.path(), false).unwrap();
+        let loaded: Vec<_> = storage
+            .load()
+            .unwrap()
+            .into_iter()
+            .map(|(key, object)| (key, object.value, object.version))
+            .collect();
+        assert_eq!(
+            loaded,
+            vec![
+                (b"a".to_vec(), SVMPrimitives::U24(3), 2),
+                (b"b".to_vec(), SVMPrimitives::U24(2), 1)
+            ]
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use crate::block::store::BLOCKS_FILE;
+use crate::block_stm::svm_memory::SVMMemory;
+use crate::svm::{object::SVMObject, primitive_types::SVMPrimitives};
+use checkpoint::list_checkpoints;
+use dashmap::DashMap;
+use export::import_snapshot;
+use file::{FileStorage, OBJECTS_FILE};
+use log::info;
+use serde::Serialize;
+use std::{fs, io, path::Path, sync::Arc};
+use wal::Wal;
+
+pub mod checkpoint;
+pub mod export;
+pub mod file;
+pub mod record_log;
+pub mod wal;
+
+pub const WAL_DIR: &str = "wal";
+
+/// Single-file log written before the log was split into segments.
+const LEGACY_WAL_FILE: &str = "wal.log";
+
+pub type StoredObject = (Vec<u8>, SVMObject<SVMPrimitives>);
+
+/// Durable home of the objects `SVMMemory` serves from memory.
+///
+/// `SVMMemory` hands every commit's write set, with the new versions, to
+/// `write_batch` and warms itself up from `load` at startup.
+pub trait Storage: Send + Sync {
+    /// Latest version of every stored object.
+    fn load(&self) -> io::Result<Vec<StoredObject>>;
+
+    /// Persists the objects written by one commit.
+    fn write_batch(&self, objects: &[StoredObject]) -> io::Result<()>;
+
+    /// Replaces what is stored with `objects`, the full state at a
+    /// checkpoint. Batches written since may be dropped, the write-ahead log
+    /// still has them.
+    fn compact(&self, _objects: &[StoredObject]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps objects in memory only, everything is gone on restart.
+#[derive(Default)]
+pub struct MemoryStorage {
+    objects: DashMap<Vec<u8>, SVMObject<SVMPrimitives>>,
+}
+
+impl Storage for MemoryStorage {
+    fn load(&self) -> io::Result<Vec<StoredObject>> {
+        Ok(self
+            .objects
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect())
+    }
+
+    fn write_batch(&self, objects: &[StoredObject]) -> io::Result<()> {
+        for (key, object) in objects {
+            self.objects.insert(key.clone(), object.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Backs `tm` with the file storage and write-ahead log in `dir`, restoring
+/// the state they hold: stored objects first, or the latest checkpoint if the
+/// storage is empty, then every logged commit the storage missed before a crash.
+pub fn open_durable(tm: SVMMemory, dir: &Path, sync: bool) -> io::Result<SVMMemory> {
+    let storage = FileStorage::open(dir, sync)?;
+    let wal_dir = dir.join(WAL_DIR);
+    let legacy_wal = dir.join(LEGACY_WAL_FILE);
+    if legacy_wal.exists() && !wal_dir.exists() {
+        fs::create_dir_all(&wal_dir)?;
+        fs::rename(&legacy_wal, wal_dir.join("00000000.log"))?;
+    }
+    let (wal, records) = Wal::open(&wal_dir, sync)?;
+    let logged = records.len();
+
+    let tm = tm.with_storage(Arc::new(storage));
+    let mut checkpoints = list_checkpoints(dir)?;
+    // the log may be pruned up to the newest checkpoint, commits from now on
+    // must still be numbered after it
+    if let Some((seq, _)) = checkpoints.last() {
+        tm.advance_past(*seq);
+    }
+    let mut loaded = tm.load()?;
+    if loaded == 0 {
+        if let Some((_, path)) = checkpoints.pop() {
+            let header = import_snapshot(&tm, &path)?;
+            info!(
+                "restored checkpoint seq={} state_root={}",
+                header.seq, header.state_root
+            );
+            loaded = header.objects as usize;
+        }
+    }
+    let tm = tm.with_wal(Arc::new(wal));
+    let replayed = tm.replay(records)?;
+    info!(
+        "restored state from dir={} objects={} logged_commits={} replayed_commits={}",
+        dir.display(),
+        loaded,
+        logged,
+        replayed
+    );
+    Ok(tm)
+}
+
+/// Bytes taken by each part of a data directory.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct DiskUsage {
+    pub objects_bytes: u64,
+    pub wal_bytes: u64,
+    pub wal_segments: usize,
+    pub blocks_bytes: u64,
+    /// commit seq and size of every checkpoint, oldest first
+    pub checkpoints: Vec<(u64, u64)>,
+    pub total_bytes: u64,
+}
+
+pub fn disk_usage(dir: &Path) -> io::Result<DiskUsage> {
+    let mut usage = DiskUsage {
+        objects_bytes: file_size(&dir.join(OBJECTS_FILE))?,
+        blocks_bytes: file_size(&dir.join(BLOCKS_FILE))?,
+        ..Default::default()
+    };
+    if dir.join(WAL_DIR).exists() {
+        for entry in fs::read_dir(dir.join(WAL_DIR))? {
+            usage.wal_bytes += entry?.metadata()?.len();
+            usage.wal_segments += 1;
+        }
+    }
+    for (seq, path) in list_checkpoints(dir)? {
+        usage.checkpoints.push((seq, file_size(&path)?));
+    }
+    usage.total_bytes = usage.objects_bytes
+        + usage.wal_bytes
+        + usage.blocks_bytes
+        + usage
+            .checkpoints
+            .iter()
+            .map(|(_, bytes)| bytes)
+            .sum::<u64>();
+    Ok(usage)
+}
+
+/// Size of the file at `path`, 0 if there is none.
+fn file_size(path: &Path) -> io::Result<u64> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e),
+    }
+}
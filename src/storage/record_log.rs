@@ -0,0 +1,250 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+/// Bytes in front of every record: payload length and CRC32 of the payload.
+pub(crate) const HEADER_LEN: usize = 8;
+
+/// Append-only file of length-prefixed, checksummed records.
+///
+/// A crash can leave the last record half written. Opening the log reads every
+/// intact record and cuts the file right after the last one.
+pub struct RecordLog {
+    path: PathBuf,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl RecordLog {
+    /// Opens or creates the log, returning it with every intact record.
+    pub fn open(path: &Path) -> io::Result<(Self, Vec<Vec<u8>>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let (records, intact_len) = read_records(&mut file)?;
+        if intact_len < file.metadata()?.len() {
+            file.set_len(intact_len)?;
+        }
+        file.seek(SeekFrom::End(0))?;
+
+        let log = Self {
+            path: path.to_path_buf(),
+            writer: Mutex::new(BufWriter::new(file)),
+        };
+        Ok((log, records))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends one record and hands it to the OS, fsyncing as well if `sync`.
+    pub fn append(&self, record: &[u8], sync: bool) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        write_record(&mut *writer, record)?;
+        writer.flush()?;
+        if sync {
+            writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Atomically replaces the whole log with `records`.
+    pub fn rewrite<I>(&self, records: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        let mut writer = self.writer.lock().unwrap();
+        let mut file = RecordWriter::create(&self.path)?;
+        for record in records {
+            file.append(&record)?;
+        }
+        file.finish()?;
+        let file = OpenOptions::new().append(true).open(&self.path)?;
+        *writer = BufWriter::new(file);
+        Ok(())
+    }
+
+    /// Size of the log file in bytes.
+    pub fn size(&self) -> io::Result<u64> {
+        Ok(fs::metadata(&self.path)?.len())
+    }
+}
+
+/// Writes a new record file next to `path` and moves it into place on
+/// [`RecordWriter::finish`], so readers never see a half-written file.
+pub struct RecordWriter {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl RecordWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let tmp_path = tmp_path(path);
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer: BufWriter::new(File::create(&tmp_path)?),
+            tmp_path,
+        })
+    }
+
+    pub fn append(&mut self, record: &[u8]) -> io::Result<()> {
+        write_record(&mut self.writer, record)
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+        fs::rename(&self.tmp_path, &self.path)?;
+        sync_dir(parent_dir(&self.path))
+    }
+}
+
+/// Fsyncs the directory `dir`, so files created, renamed or deleted in it
+/// are still there after a crash.
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Unique file next to `path` to write it in before moving it into place.
+/// Files only differing in their extension get different ones.
+pub fn tmp_path(path: &Path) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(
+        ".{}.{}.tmp",
+        process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    path.with_file_name(name)
+}
+
+/// Reads every record of a file written by [`RecordWriter`], failing if any is torn or corrupt.
+pub fn read_file(path: &Path) -> io::Result<Vec<Vec<u8>>> {
+    let mut file = File::open(path)?;
+    let (records, intact_len) = read_records(&mut file)?;
+    if intact_len < file.metadata()?.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "corrupt record at offset={} in {}",
+                intact_len,
+                path.display()
+            ),
+        ));
+    }
+    Ok(records)
+}
+
+fn write_record<W: Write>(writer: &mut W, record: &[u8]) -> io::Result<()> {
+    writer.write_all(&(record.len() as u32).to_le_bytes())?;
+    writer.write_all(&crc32fast::hash(record).to_le_bytes())?;
+    writer.write_all(record)
+}
+
+/// Reads records from the start of `file` until the end or the first torn or
+/// corrupt one. Returns them with the length of the intact prefix.
+fn read_records(file: &mut File) -> io::Result<(Vec<Vec<u8>>, u64)> {
+    let file_len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(file);
+    let mut records = vec![];
+    let mut intact_len = 0u64;
+
+    loop {
+        let mut header = [0u8; HEADER_LEN];
+        if read_full(&mut reader, &mut header)? < HEADER_LEN {
+            break;
+        }
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+        // a length past the end of the file comes from a torn or corrupt
+        // header, checked before it is allocated
+        if len as u64 > file_len - intact_len - HEADER_LEN as u64 {
+            break;
+        }
+        let mut record = vec![0u8; len];
+        if read_full(&mut reader, &mut record)? < len || crc32fast::hash(&record) != crc {
+            break;
+        }
+        intact_len += (HEADER_LEN + len) as u64;
+        records.push(record);
+    }
+
+    Ok((records, intact_len))
+}
+
+/// Like `read_exact`, but returns how much was read instead of failing at EOF.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn torn_tail_is_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("records.log");
+        {
+            let (log, records) = RecordLog::open(&path).unwrap();
+            assert!(records.is_empty());
+            log.append(b"first", false).unwrap();
+            log.append(b"second", true).unwrap();
+        }
+        let intact_len = fs::metadata(&path).unwrap().len();
+
+        // half of a third record made it to disk before the crash
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write_record(&mut file, b"third").unwrap();
+        file.set_len(intact_len + 6).unwrap();
+        drop(file);
+
+        let (log, records) = RecordLog::open(&path).unwrap();
+        assert_eq!(records, vec![b"first".to_vec(), b"second".to_vec()]);
+        assert_eq!(log.size().unwrap(), intact_len);
+
+        log.append(b"fourth", false).unwrap();
+        let (log, records) = RecordLog::open(&path).unwrap();
+        assert_eq!(records.len(), 3);
+        let intact_len = log.size().unwrap();
+        drop(log);
+
+        // a header claiming far more bytes than the file holds
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        file.write_all(&[0; 12]).unwrap();
+        drop(file);
+        let (log, records) = RecordLog::open(&path).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(log.size().unwrap(), intact_len);
+    }
+}
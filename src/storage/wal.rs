@@ -0,0 +1,236 @@
+use super::{
+    file::to_io_error,
+    record_log::{sync_dir, RecordLog, HEADER_LEN},
+    StoredObject,
+};
+use crate::executor::types::TxBody;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// One committed transaction, logged before any of its writes are applied.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WalRecord {
+    /// commit sequence the transaction got
+    pub seq: u64,
+    /// `None` for writes that did not come from a submitted transaction, e.g. allocations
+    pub tx: Option<TxBody>,
+    /// every object the commit writes, with its new version
+    pub writes: Vec<StoredObject>,
+}
+
+/// Segments are rolled over once they grow past this many bytes.
+pub const DEFAULT_SEGMENT_BYTES: u64 = 64 << 20;
+
+/// Append-only write-ahead log of committed transactions, split into
+/// numbered segment files so history covered by a checkpoint can be dropped
+/// a file at a time. Every record is checksummed, so after a crash only whole
+/// transactions are replayed and a torn last record is dropped.
+pub struct Wal {
+    dir: PathBuf,
+    /// fsync every record instead of only handing it to the OS
+    sync: bool,
+    segment_bytes: u64,
+    segments: Mutex<Segments>,
+}
+
+struct Segments {
+    /// full segments, oldest first, with the highest sequence they hold
+    sealed: Vec<(PathBuf, u64)>,
+    active: RecordLog,
+    active_index: u64,
+    active_bytes: u64,
+    active_last_seq: u64,
+}
+
+impl Wal {
+    /// Opens or creates the log in `dir`, returning it with every intact
+    /// record, segment by segment.
+    pub fn open(dir: &Path, sync: bool) -> io::Result<(Self, Vec<WalRecord>)> {
+        fs::create_dir_all(dir)?;
+        let mut indexes = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "log") {
+                if let Some(index) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u64>().ok())
+                {
+                    indexes.push(index);
+                }
+            }
+        }
+        indexes.sort_unstable();
+        let active_index = indexes.pop().unwrap_or(0);
+
+        let mut records = vec![];
+        let mut sealed = vec![];
+        for index in indexes {
+            let path = segment_path(dir, index);
+            let (_, segment) = RecordLog::open(&path)?;
+            let segment = decode(&segment)?;
+            let last_seq = segment.iter().map(|record| record.seq).max().unwrap_or(0);
+            sealed.push((path, last_seq));
+            records.extend(segment);
+        }
+        let (active, segment) = RecordLog::open(&segment_path(dir, active_index))?;
+        let segment = decode(&segment)?;
+        let active_last_seq = segment.iter().map(|record| record.seq).max().unwrap_or(0);
+        records.extend(segment);
+
+        let segments = Segments {
+            sealed,
+            active_bytes: active.size()?,
+            active,
+            active_index,
+            active_last_seq,
+        };
+        let wal = Self {
+            dir: dir.to_path_buf(),
+            sync,
+            segment_bytes: DEFAULT_SEGMENT_BYTES,
+            segments: Mutex::new(segments),
+        };
+        Ok((wal, records))
+    }
+
+    pub fn with_segment_bytes(mut self, segment_bytes: u64) -> Self {
+        self.segment_bytes = segment_bytes;
+        self
+    }
+
+    pub fn append(&self, record: &WalRecord) -> io::Result<()> {
+        let seq = record.seq;
+        let record = bincode::serialize(record).map_err(to_io_error)?;
+        let mut segments = self.segments.lock().unwrap();
+        segments.active.append(&record, self.sync)?;
+        segments.active_bytes += (HEADER_LEN + record.len()) as u64;
+        segments.active_last_seq = segments.active_last_seq.max(seq);
+
+        if segments.active_bytes >= self.segment_bytes {
+            let index = segments.active_index + 1;
+            let (active, _) = RecordLog::open(&segment_path(&self.dir, index))?;
+            if self.sync {
+                sync_dir(&self.dir)?;
+            }
+            let full = std::mem::replace(&mut segments.active, active);
+            let last_seq = segments.active_last_seq;
+            segments.sealed.push((full.path().to_path_buf(), last_seq));
+            segments.active_index = index;
+            segments.active_bytes = 0;
+            segments.active_last_seq = 0;
+        }
+        Ok(())
+    }
+
+    /// Deletes every full segment whose records all have a sequence up to
+    /// `seq`. Returns how many were deleted.
+    pub fn prune(&self, seq: u64) -> io::Result<usize> {
+        let mut segments = self.segments.lock().unwrap();
+        let mut pruned = 0;
+        while let Some((path, last_seq)) = segments.sealed.first() {
+            if *last_seq > seq {
+                break;
+            }
+            fs::remove_file(path)?;
+            segments.sealed.remove(0);
+            pruned += 1;
+        }
+        Ok(pruned)
+    }
+
+    /// Number of segment files, the one being appended to included.
+    pub fn segments(&self) -> usize {
+        self.segments.lock().unwrap().sealed.len() + 1
+    }
+}
+
+fn segment_path(dir: &Path, index: u64) -> PathBuf {
+    dir.join(format!("{:08}.log", index))
+}
+
+fn decode(records: &[Vec<u8>]) -> io::Result<Vec<WalRecord>> {
+    records
+        .iter()
+        .map(|record| bincode::deserialize(record).map_err(to_io_error))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block_stm::svm_memory::{retry_transaction, SVMMemory};
+    use crate::storage::open_durable;
+    use crate::svm::primitive_types::SVMPrimitives;
+    use std::{
+        env,
+        io::{BufRead, BufReader},
+        path::Path,
+        process::{Command, Stdio},
+        sync::Arc,
+    };
+
+    const CRASH_DIR_ENV: &str = "SVM_WAL_CRASH_DIR";
+    const KEYS_PER_TX: u32 = 8;
+    /// printed by the child once its first transaction committed
+    const COMMITTED: &str = "crash_child committed";
+
+    fn key(i: u32) -> Vec<u8> {
+        format!("0x{}", i).into_bytes()
+    }
+
+    /// Commits transactions that each write the same value to all keys,
+    /// forever, saying so after the first. Only does something when spawned
+    /// by `kill_mid_batch`.
+    #[test]
+    fn crash_child() {
+        let Ok(dir) = env::var(CRASH_DIR_ENV) else {
+            return;
+        };
+        let tm = Arc::new(open_durable(SVMMemory::new(), Path::new(&dir), false).unwrap());
+        for i in 1.. {
+            retry_transaction(tm.clone(), |txn| {
+                for k in 0..KEYS_PER_TX {
+                    txn.write(key(k), SVMPrimitives::U24(i));
+                }
+                Ok(SVMPrimitives::Era)
+            })
+            .unwrap();
+            if i == 1 {
+                println!("{}", COMMITTED);
+            }
+        }
+    }
+
+    #[test]
+    fn kill_mid_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        for round in 0..3 {
+            let mut child = Command::new(env::current_exe().unwrap())
+                .args(["--exact", "storage::wal::tests::crash_child", "--nocapture"])
+                .env(CRASH_DIR_ENV, dir.path())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            let stdout = BufReader::new(child.stdout.take().unwrap());
+            let committed = stdout
+                .lines()
+                .any(|line| line.unwrap().ends_with(COMMITTED));
+            assert!(committed, "child exited before committing");
+            child.kill().unwrap();
+            child.wait().unwrap();
+
+            let tm = open_durable(SVMMemory::new(), dir.path(), false).unwrap();
+            let objects: Vec<_> = (0..KEYS_PER_TX)
+                .map(|k| tm.get(key(k)).expect("key was never committed"))
+                .collect();
+            for object in &objects {
+                assert_eq!(object.value, objects[0].value, "round={}", round);
+                assert_eq!(object.version, objects[0].version, "round={}", round);
+            }
+        }
+    }
+}
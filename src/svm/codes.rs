@@ -0,0 +1,81 @@
+use crate::storage::record_log::tmp_path;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Codes deployed while the node runs, in the data dir, so a restart deploys
+/// them again before any transaction needs them.
+pub const CODES_DIR: &str = "codes";
+
+/// File of `code_id`, hex encoded as code ids are not valid file names.
+fn code_path(dir: &Path, code_id: &str) -> PathBuf {
+    dir.join(CODES_DIR)
+        .join(format!("{}.bend", hex::encode(code_id)))
+}
+
+/// Stores the Bend source of a deployed code in the data dir `dir`.
+pub fn save_code(dir: &Path, code_id: &str, source: &str) -> io::Result<()> {
+    fs::create_dir_all(dir.join(CODES_DIR))?;
+    let path = code_path(dir, code_id);
+    let tmp = tmp_path(&path);
+    fs::write(&tmp, source)?;
+    fs::rename(&tmp, &path)
+}
+
+/// Codes stored in the data dir `dir` with their sources, by id.
+pub fn load_codes(dir: &Path) -> io::Result<Vec<(String, String)>> {
+    let dir = dir.join(CODES_DIR);
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut codes = vec![];
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "bend") {
+            continue;
+        }
+        let code_id = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| hex::decode(stem).ok())
+            .and_then(|id| String::from_utf8(id).ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid code file path={}", path.display()),
+                )
+            })?;
+        codes.push((code_id, fs::read_to_string(&path)?));
+    }
+    codes.sort();
+    Ok(codes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_codes_load_by_id() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_codes(dir.path()).unwrap().is_empty());
+
+        save_code(dir.path(), "0xgame/v1", "def main(a):\n  return a\n").unwrap();
+        save_code(dir.path(), "0xcounter", "def main(a):\n  return 1\n").unwrap();
+        save_code(dir.path(), "0xcounter", "def main(a):\n  return 2\n").unwrap();
+        assert_eq!(
+            load_codes(dir.path()).unwrap(),
+            vec![
+                (
+                    "0xcounter".to_string(),
+                    "def main(a):\n  return 2\n".to_string()
+                ),
+                (
+                    "0xgame/v1".to_string(),
+                    "def main(a):\n  return a\n".to_string()
+                ),
+            ]
+        );
+    }
+}
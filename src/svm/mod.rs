@@ -1,4 +1,6 @@
 pub mod builtins;
+pub mod codes;
 pub mod object;
 pub mod primitive_types;
+#[allow(clippy::module_inception)]
 pub mod svm;
@@ -1,6 +1,8 @@
+use serde::{Deserialize, Serialize};
+
 pub type Version = u64;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SVMObject<T> {
     pub value: T,
     pub version: Version,
@@ -3,7 +3,7 @@ use bend::fun::Term;
 use log::error;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SVMPrimitives {
     U24(u32),
     Tup(Vec<SVMPrimitives>),
@@ -2,6 +2,7 @@ use super::builtins::{
     self, DUANGUA3_CODE, DUANGUA3_CODE_ID, DUANGUA_CODE, DUANGUA_CODE_ID, TRANSFER_CODE,
     TRANSFER_CODE_ID,
 };
+use crate::block_stm::svm_memory::ConcurrencyMode;
 use bend::{
     compile_book,
     diagnostics::{Diagnostics, DiagnosticsConfig},
@@ -10,11 +11,48 @@ use bend::{
 };
 use builtins::{ADD_CODE, ADD_CODE_ID, SUB_CODE, SUB_CODE_ID};
 use hvm::hvm::{GNet, TMem};
-use log::info;
-use std::{collections::HashMap, path::Path, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::Path,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+/// What one HVM run cost.
+#[derive(Clone, Debug)]
+pub struct RunStats {
+    /// interactions HVM performed, the gas of a transaction
+    pub interactions: u64,
+    pub duration: Duration,
+}
+
+impl fmt::Display for RunStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            r#"- ITRS: {}
+- TIME: {:.2}s
+- MIPS: {:.2}"#,
+            self.interactions,
+            self.duration.as_secs_f64(),
+            self.interactions as f64 / self.duration.as_secs_f64() / 1_000_000.0
+        )
+    }
+}
 
 pub struct SVM {
-    books: Arc<HashMap<String, Book>>,
+    /// codes by id, more can be deployed while the node runs
+    books: RwLock<HashMap<String, Book>>,
+    /// concurrency mode used for codes without an override
+    default_mode: ConcurrencyMode,
+    code_modes: HashMap<String, ConcurrencyMode>,
+}
+
+impl Default for SVM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SVM {
@@ -36,18 +74,75 @@ impl SVM {
         }
 
         Self {
-            books: Arc::new(books),
+            books: RwLock::new(books),
+            default_mode: ConcurrencyMode::default(),
+            code_modes: HashMap::new(),
         }
     }
 
+    pub fn with_default_mode(mut self, mode: ConcurrencyMode) -> Self {
+        self.default_mode = mode;
+        self
+    }
+
+    pub fn with_code_mode(mut self, code_id: &str, mode: ConcurrencyMode) -> Self {
+        self.code_modes.insert(code_id.to_string(), mode);
+        self
+    }
+
+    /// Deploys the Bend `code` under `code_id`, replacing any code with that id.
+    pub fn with_code(self, code_id: &str, code: &str) -> Result<Self, String> {
+        let book = parse_code(code_id, code)?;
+        self.books
+            .write()
+            .unwrap()
+            .insert(code_id.to_string(), book);
+        Ok(self)
+    }
+
+    /// Deploys the Bend `code` under `code_id` on a running node, once
+    /// `save` has stored it. Codes already deployed are never replaced, as
+    /// past transactions ran them.
+    pub fn deploy<F>(&self, code_id: &str, code: &str, save: F) -> io::Result<()>
+    where
+        F: FnOnce() -> io::Result<()>,
+    {
+        let book = parse_code(code_id, code)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut books = self.books.write().unwrap();
+        if books.contains_key(code_id) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("code id={} is already deployed", code_id),
+            ));
+        }
+        save()?;
+        books.insert(code_id.to_string(), book);
+        Ok(())
+    }
+
+    pub fn has_code(&self, code_id: &str) -> bool {
+        self.books.read().unwrap().contains_key(code_id)
+    }
+
+    pub fn concurrency_mode(&self, code_id: &str) -> ConcurrencyMode {
+        *self.code_modes.get(code_id).unwrap_or(&self.default_mode)
+    }
+
     pub fn run_code(
         self: Arc<Self>,
         code_id: &str,
         // TODO(rameight): HVM2 doesn't enable entrypoint running yet.
         // entrypoint: Option<&str>,
         arguments: Option<Vec<Term>>,
-    ) -> Result<(Term, String, Diagnostics), Diagnostics> {
-        let book = self.books.get(code_id).expect("load book failed").clone();
+    ) -> Result<(Term, RunStats, Diagnostics), Diagnostics> {
+        let book = self
+            .books
+            .read()
+            .unwrap()
+            .get(code_id)
+            .expect("load book failed")
+            .clone();
         let run_opts = RunOpts {
             linear_readback: false,
             pretty: false,
@@ -85,7 +180,7 @@ impl SVM {
         compile_opts: CompileOpts,
         diagnostics_cfg: DiagnosticsConfig,
         args: Option<Vec<Term>>,
-    ) -> Result<(Term, String, Diagnostics), Diagnostics> {
+    ) -> Result<(Term, RunStats, Diagnostics), Diagnostics> {
         let CompileResult {
             hvm_book: core_book,
             labels,
@@ -105,7 +200,7 @@ impl SVM {
         Ok((term, stats, diags))
     }
 
-    pub fn run_hvm(book: &hvm::hvm::Book) -> Result<(hvm::ast::Net, String), String> {
+    pub fn run_hvm(book: &hvm::hvm::Book) -> Result<(hvm::ast::Net, RunStats), String> {
         // Initializes the global net
         let net = GNet::new(1 << 29, 1 << 29);
 
@@ -124,27 +219,20 @@ impl SVM {
         let start = std::time::Instant::now();
 
         // Evaluates
-        tm.evaluator(&net, &book);
+        tm.evaluator(&net, book);
 
         // Stops the timer
         let duration = start.elapsed();
 
         // Prints interactions and time
-        let stats = {
-            let itrs = net.itrs.load(std::sync::atomic::Ordering::Relaxed);
-            format!(
-                r#"- ITRS: {}
-- TIME: {:.2}s
-- MIPS: {:.2}"#,
-                itrs,
-                duration.as_secs_f64(),
-                itrs as f64 / duration.as_secs_f64() / 1_000_000.0
-            )
+        let stats = RunStats {
+            interactions: net.itrs.load(std::sync::atomic::Ordering::Relaxed),
+            duration,
         };
 
         // Parse the result
         let result = if let Some(tree) = hvm::ast::Net::readback(&net, book) {
-            format!("{}", tree.show())
+            tree.show().to_string()
         } else {
             format!(
                 r#"Readback failed. Printing GNet memdump...
@@ -180,3 +268,8 @@ impl SVM {
         run_book(book, run_opts, compile_opts, diagnostics_cfg, args, "run")
     }
 }
+
+fn parse_code(code_id: &str, code: &str) -> Result<Book, String> {
+    do_parse_book(code, Path::new(""), fun::Book::builtins())
+        .map_err(|e| format!("failed to load code id={} err={}", code_id, e))
+}
@@ -0,0 +1,83 @@
+use crate::block::{
+    builder::{BlockConfig, BlockProducer, Execute},
+    store::BlockStore,
+};
+use crate::block_stm::svm_memory::{execute_transaction, ConcurrencyMode, SVMMemory};
+use crate::executor::{
+    engine::{EngineConfig, ExecutionEngine},
+    types::TxBody,
+    Executed,
+};
+use crate::genesis::Genesis;
+use crate::svm::{primitive_types::SVMPrimitives, svm::SVM};
+use crate::ws::{
+    auth::AdminAuth,
+    limits::{LimitConfig, Limits},
+    Node,
+};
+use std::sync::{atomic::AtomicBool, Arc};
+
+/// Token admin messages of a test node need.
+pub(crate) const ADMIN_TOKEN: &str = "s3cret";
+
+pub(crate) fn tx(i: u32) -> TxBody {
+    TxBody {
+        tx_hash: format!("0xtx{}", i),
+        code_hash: "0xincrement".to_string(),
+        objs: vec!["0xcounter".to_string()],
+        args: vec![SVMPrimitives::U24(i)],
+    }
+}
+
+/// Adds the tx's argument to the counter, failing for odd arguments.
+pub(crate) fn increment(tm: &Arc<SVMMemory>, tx_body: &TxBody) -> Executed {
+    let SVMPrimitives::U24(amount) = tx_body.args[0] else {
+        unreachable!()
+    };
+    let result = execute_transaction(tm.clone(), ConcurrencyMode::Optimistic, &[], |txn| {
+        if amount % 2 == 1 {
+            return Err("odd amount".to_string());
+        }
+        let current = match txn.read(b"0xcounter".to_vec()) {
+            Some(SVMPrimitives::U24(current)) => current,
+            _ => 0,
+        };
+        txn.write(b"0xcounter".to_vec(), SVMPrimitives::U24(current + amount));
+        Ok(SVMPrimitives::U24(current + amount))
+    });
+    Executed {
+        result,
+        gas: amount as u64,
+    }
+}
+
+/// Node over `tm` with default limits and no data dir. Its blocks run
+/// transactions with `execute`, or on its SVM without one.
+pub(crate) fn test_node(
+    tm: Arc<SVMMemory>,
+    engine: EngineConfig,
+    execute: Option<Execute>,
+) -> Node {
+    let svm = Arc::new(SVM::new());
+    let engine = Arc::new(ExecutionEngine::new(&engine));
+    let store = Arc::new(BlockStore::default());
+    let config = BlockConfig::default();
+    let blocks = match execute {
+        Some(execute) => {
+            BlockProducer::spawn_with(tm.clone(), store, config, engine.clone(), execute)
+        }
+        None => BlockProducer::spawn(tm.clone(), svm.clone(), store, config, engine.clone()),
+    };
+    Node {
+        tm,
+        svm,
+        engine,
+        blocks: Arc::new(blocks),
+        genesis: Arc::new(Genesis::default()),
+        data_dir: None,
+        snapshot_dir: None,
+        limits: Arc::new(Limits::new(LimitConfig::default())),
+        admin: AdminAuth::new(ADMIN_TOKEN),
+        paused: AtomicBool::new(false),
+    }
+}
@@ -0,0 +1,81 @@
+use super::events::{ApiError, ErrorCode};
+use crate::merkle::Hash;
+use sha2::{Digest, Sha256};
+use std::{fs, io, path::Path};
+
+/// The admin token of the node, read from the file in its config. Without
+/// one, admin messages are refused to everyone.
+#[derive(Default)]
+pub struct AdminAuth {
+    /// hash of the token, so checks take the same time however much of a
+    /// guess matches
+    token: Option<Hash>,
+}
+
+impl AdminAuth {
+    pub fn new(token: &str) -> Self {
+        Self {
+            token: Some(Sha256::digest(token.as_bytes()).into()),
+        }
+    }
+
+    /// Reads the token from the first line of `path`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let token = text.lines().next().unwrap_or_default().trim();
+        if token.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("admin token file={} is empty", path.display()),
+            ));
+        }
+        Ok(Self::new(token))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.token.is_some()
+    }
+
+    /// Whether `token` is the admin token.
+    pub fn check(&self, token: &str) -> bool {
+        let Some(expected) = &self.token else {
+            return false;
+        };
+        let given: Hash = Sha256::digest(token.as_bytes()).into();
+        given
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
+}
+
+pub fn unauthorized(kind: &str) -> ApiError {
+    ApiError::new(
+        ErrorCode::Unauthorized,
+        format!(
+            "{} is an admin message, authenticate with the admin token first",
+            kind
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_token_in_the_file_is_admin() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("admin.token");
+        fs::write(&path, "s3cret\n").unwrap();
+        let auth = AdminAuth::load(&path).unwrap();
+        assert!(auth.check("s3cret"));
+        assert!(!auth.check("s3cre"));
+        assert!(!auth.check(""));
+
+        assert!(!AdminAuth::default().check(""));
+        fs::write(&path, "\n").unwrap();
+        assert!(AdminAuth::load(&path).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use super::events::{ApiError, ErrorCode, Message, Response, PROTOCOL_VERSION};
+use crate::block_stm::svm_memory::CommitSeq;
+use crate::codec;
+use crate::executor::types::{Receipt, TxBody, TxResult};
+use crate::svm::{object::Version, primitive_types::SVMPrimitives};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Number, Value};
+
+/// WebSocket subprotocol of connections that send and receive envelopes in
+/// the binary codec, over binary frames.
+pub const BINARY_PROTOCOL: &str = "svm.bincode.v1";
+
+/// How a connection encodes envelopes, chosen by the client when it connects.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    Binary,
+}
+
+impl Encoding {
+    /// Binary if the client offers `BINARY_PROTOCOL` in its
+    /// `Sec-WebSocket-Protocol` header.
+    pub fn negotiate(offered: Option<&str>) -> Self {
+        let offered = offered.unwrap_or_default();
+        if offered
+            .split(',')
+            .any(|protocol| protocol.trim() == BINARY_PROTOCOL)
+        {
+            Encoding::Binary
+        } else {
+            Encoding::Json
+        }
+    }
+}
+
+/// `Request` in a binary frame, with the message itself instead of its
+/// `type` and `body`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BinaryRequest {
+    pub v: u32,
+    pub id: String,
+    pub message: Message,
+}
+
+impl BinaryRequest {
+    pub fn decode(bytes: &[u8]) -> Result<Self, ApiError> {
+        codec::decode(bytes).map_err(|e| {
+            ApiError::new(
+                ErrorCode::InvalidRequest,
+                format!("invalid binary request err={}", e),
+            )
+        })
+    }
+
+    pub fn message(self) -> Result<Message, ApiError> {
+        if self.v != PROTOCOL_VERSION {
+            return Err(ApiError::new(
+                ErrorCode::UnsupportedVersion,
+                format!("v={} is not supported, use v={}", self.v, PROTOCOL_VERSION),
+            ));
+        }
+        Ok(self.message)
+    }
+}
+
+/// `Response` in a binary frame.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BinaryResponse {
+    pub v: u32,
+    pub id: Option<String>,
+    pub kind: String,
+    pub result: Result<BinaryResult, ApiError>,
+}
+
+impl From<Response> for BinaryResponse {
+    fn from(response: Response) -> Self {
+        let result = match response.error {
+            Some(error) => Err(error),
+            None => Ok(BinaryResult::new(
+                &response.kind,
+                response.result.unwrap_or_default(),
+            )),
+        };
+        Self {
+            v: response.v,
+            id: response.id,
+            kind: response.kind,
+            result,
+        }
+    }
+}
+
+/// Result of a binary response. Transactions, receipts and values come
+/// typed, so they travel in the canonical encoding of their types; results
+/// of other messages come as `Other`. Variants are named after the `kind` of
+/// the response.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub enum BinaryResult {
+    Accepted {
+        tx_hashes: Vec<String>,
+    },
+    SubmitTx(TxResult),
+    SubmitBatch {
+        results: Vec<TxResult>,
+    },
+    Simulate(Receipt),
+    /// without a proof
+    GetValueAt {
+        addr: String,
+        at: Option<CommitSeq>,
+        value: Option<SVMPrimitives>,
+    },
+    /// a transaction pushed to `SubscribeTxs`
+    Tx {
+        subscription: u64,
+        height: u64,
+        tx_seq: u64,
+        tx: TxBody,
+        receipt: Receipt,
+    },
+    Changes {
+        subscription: u64,
+        seq: CommitSeq,
+        changes: Vec<Change>,
+    },
+    Other(WireValue),
+}
+
+/// An object written by a commit, as pushed in `Changes`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Change {
+    pub addr: String,
+    pub value: SVMPrimitives,
+    pub version: Version,
+}
+
+impl BinaryResult {
+    /// The typed variant for results of `kind`, if `result` has its shape.
+    pub fn new(kind: &str, result: Value) -> Self {
+        match serde_json::from_value(json!({ kind: &result })) {
+            Ok(BinaryResult::Other(_)) | Err(_) => BinaryResult::Other(result.into()),
+            Ok(typed) => typed,
+        }
+    }
+}
+
+impl From<BinaryResult> for Value {
+    fn from(result: BinaryResult) -> Self {
+        match result {
+            BinaryResult::Other(value) => value.into(),
+            typed => match serde_json::to_value(typed).unwrap() {
+                Value::Object(tagged) => tagged.into_iter().next().unwrap().1,
+                _ => unreachable!("results are tagged with their kind"),
+            },
+        }
+    }
+}
+
+/// A JSON value in a form the binary codec can decode, which `Value`
+/// cannot as it does not say what type comes next.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum WireValue {
+    Null,
+    Bool(bool),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    String(String),
+    Array(Vec<WireValue>),
+    /// entries in key order
+    Object(Vec<(String, WireValue)>),
+}
+
+impl From<Value> for WireValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => WireValue::Null,
+            Value::Bool(value) => WireValue::Bool(value),
+            Value::Number(number) => match (number.as_u64(), number.as_i64()) {
+                (Some(value), _) => WireValue::U64(value),
+                (None, Some(value)) => WireValue::I64(value),
+                _ => WireValue::F64(number.as_f64().unwrap_or_default()),
+            },
+            Value::String(value) => WireValue::String(value),
+            Value::Array(values) => WireValue::Array(values.into_iter().map(Into::into).collect()),
+            Value::Object(entries) => WireValue::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<WireValue> for Value {
+    fn from(value: WireValue) -> Self {
+        match value {
+            WireValue::Null => Value::Null,
+            WireValue::Bool(value) => Value::Bool(value),
+            WireValue::U64(value) => Value::from(value),
+            WireValue::I64(value) => Value::from(value),
+            WireValue::F64(value) => Number::from_f64(value).map_or(Value::Null, Value::Number),
+            WireValue::String(value) => Value::String(value),
+            WireValue::Array(values) => Value::Array(values.into_iter().map(Into::into).collect()),
+            WireValue::Object(entries) => Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect::<Map<String, Value>>(),
+            ),
+        }
+    }
+}
@@ -1,12 +1,194 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
-use crate::executor::types::TxBody;
+use crate::block::{builder::BatchMode, stream::TxFilter};
+use crate::executor::{engine::Busy, types::TxBody};
 
+/// Version of the request/response envelope this node speaks.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// A message in the versioned envelope. The response echoes `id`, so a
+/// client can match it with many requests in flight on one socket:
+///
+/// ```json
+/// { "v": 1, "id": "7", "type": "GetValueAt", "body": { "addr": "0x1" } }
+/// ```
+///
+/// Messages without `v` are the bare legacy form, `{ "GetValueAt": { .. } }`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Request {
+    pub v: u32,
+    /// chosen by the client
+    pub id: String,
+    /// name of a `Message` variant
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// fields of the message, may be omitted when it has none
+    #[serde(default)]
+    pub body: Value,
+}
+
+impl Request {
+    /// The message carried in the envelope.
+    pub fn message(&self) -> Result<Message, ApiError> {
+        if self.v != PROTOCOL_VERSION {
+            return Err(ApiError::new(
+                ErrorCode::UnsupportedVersion,
+                format!("v={} is not supported, use v={}", self.v, PROTOCOL_VERSION),
+            ));
+        }
+        let body = match &self.body {
+            Value::Null => json!({}),
+            body => body.clone(),
+        };
+        serde_json::from_value(json!({ self.kind.as_str(): body })).map_err(|e| {
+            ApiError::new(
+                ErrorCode::InvalidRequest,
+                format!("invalid {} err={}", self.kind, e),
+            )
+        })
+    }
+}
+
+/// Answer to a `Request`, holding either `result` or `error`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Response {
+    pub v: u32,
+    /// `id` of the request, `None` if it could not be read
+    pub id: Option<String>,
+    /// `type` of the request
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiError>,
+}
+
+impl Response {
+    pub fn new(id: Option<String>, kind: String, result: Result<Value, ApiError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            v: PROTOCOL_VERSION,
+            id,
+            kind,
+            result,
+            error,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// not an envelope, an unknown `type` or a body that does not fit it
+    InvalidRequest,
+    UnsupportedVersion,
+    /// well-formed, but cannot be served, e.g. a sequence below the gc horizon
+    InvalidArgument,
+    NotFound,
+    Internal,
+    /// the node has no room for the request right now, retry later
+    Busy,
+    /// an admin message without the admin token
+    Unauthorized,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl From<Busy> for ApiError {
+    fn from(e: Busy) -> Self {
+        ApiError::new(ErrorCode::Busy, e.to_string())
+    }
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Variants are encoded by position in binary frames, new ones go last.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
     ReallocateMemory(ReallocateMemory),
     GetValueAt(GetValueAt),
+    GetValues(GetValues),
+    ScanKeys(ScanKeys),
     SubmitTx(SubmitTx),
+    SubmitBatch(SubmitBatch),
+    Simulate(Simulate),
+    GetBlock(GetBlock),
+    GetLatestBlock(GetLatestBlock),
+    GetTxStatus(GetTxStatus),
+    Subscribe(Subscribe),
+    SubscribeTxs(SubscribeTxs),
+    SubscribeBlocks(SubscribeBlocks),
+    Unsubscribe(Unsubscribe),
+    // admin
+    GetContentionReport(GetContentionReport),
+    ExportSnapshot(ExportSnapshot),
+    ImportSnapshot(ImportSnapshot),
+    GetDiskUsage(GetDiskUsage),
+    DeployCode(DeployCode),
+    Authenticate(Authenticate),
+    Pause(Pause),
+    Resume(Resume),
+}
+
+impl Message {
+    /// Name of the variant, the `type` of its envelope.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::ReallocateMemory(_) => "ReallocateMemory",
+            Message::GetValueAt(_) => "GetValueAt",
+            Message::GetValues(_) => "GetValues",
+            Message::ScanKeys(_) => "ScanKeys",
+            Message::SubmitTx(_) => "SubmitTx",
+            Message::SubmitBatch(_) => "SubmitBatch",
+            Message::Simulate(_) => "Simulate",
+            Message::GetBlock(_) => "GetBlock",
+            Message::GetLatestBlock(_) => "GetLatestBlock",
+            Message::GetTxStatus(_) => "GetTxStatus",
+            Message::Subscribe(_) => "Subscribe",
+            Message::SubscribeTxs(_) => "SubscribeTxs",
+            Message::SubscribeBlocks(_) => "SubscribeBlocks",
+            Message::Unsubscribe(_) => "Unsubscribe",
+            Message::GetContentionReport(_) => "GetContentionReport",
+            Message::ExportSnapshot(_) => "ExportSnapshot",
+            Message::ImportSnapshot(_) => "ImportSnapshot",
+            Message::GetDiskUsage(_) => "GetDiskUsage",
+            Message::DeployCode(_) => "DeployCode",
+            Message::Authenticate(_) => "Authenticate",
+            Message::Pause(_) => "Pause",
+            Message::Resume(_) => "Resume",
+        }
+    }
+
+    /// Whether only a client holding the admin token may send it.
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            Message::ReallocateMemory(_)
+                | Message::GetContentionReport(_)
+                | Message::ExportSnapshot(_)
+                | Message::ImportSnapshot(_)
+                | Message::GetDiskUsage(_)
+                | Message::DeployCode(_)
+                | Message::Pause(_)
+                | Message::Resume(_)
+        )
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -15,9 +197,168 @@ pub struct ReallocateMemory {}
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GetValueAt {
     pub addr: String,
+    /// commit sequence to read at, latest state if omitted; the latest state
+    /// holds the writes of every transaction already answered
+    #[serde(default)]
+    pub at: Option<u64>,
+    /// also return the version, state root and a merkle proof of the value,
+    /// only for the latest state
+    #[serde(default)]
+    pub prove: bool,
+}
+
+/// Reads several addresses from one consistent snapshot.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetValues {
+    pub addrs: Vec<String>,
+    /// commit sequence to read at, latest state if omitted
+    #[serde(default)]
+    pub at: Option<u64>,
+}
+
+/// Lists objects by key prefix or `[start, end)` range, one page at a time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScanKeys {
+    #[serde(default)]
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub start: Option<String>,
+    #[serde(default)]
+    pub end: Option<String>,
+    /// `next_cursor` of the previous page
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// commit sequence to read at, latest state if omitted
+    #[serde(default)]
+    pub at: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SubmitTx {
     pub tx_body: TxBody,
 }
+
+/// Submits many transactions at once, answered with one result per
+/// transaction in the same order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubmitBatch {
+    pub txs: Vec<TxBody>,
+    #[serde(default)]
+    pub mode: BatchMode,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetBlock {
+    pub height: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetLatestBlock {}
+
+/// Runs a transaction on the latest state without committing it, answered
+/// with the receipt it would get.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Simulate {
+    pub tx_body: TxBody,
+}
+
+/// Pending, committed or failed, for a transaction submitted by any connection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetTxStatus {
+    pub tx_hash: String,
+}
+
+/// Pushes a `Changes` message with the new values and versions after every
+/// commit writing one of `keys`, or a key starting with one of `prefixes`.
+/// A subscriber too slow to keep up gets a `Lagged` message instead and is
+/// dropped; it should subscribe again and re-read the keys.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Subscribe {
+    #[serde(default)]
+    pub keys: Vec<String>,
+    #[serde(default)]
+    pub prefixes: Vec<String>,
+}
+
+/// Pushes a `Tx` message with the body and receipt of every transaction in
+/// a block that matches `filter`, numbered by `tx_seq` in chain order. To
+/// resume after a reconnect, pass the last `tx_seq` seen plus one.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubscribeTxs {
+    #[serde(default)]
+    pub filter: TxFilter,
+    /// start at the first transaction of this block
+    #[serde(default)]
+    pub from_height: Option<u64>,
+    /// start at this transaction; streams begin at the next block if neither is set
+    #[serde(default)]
+    pub from_tx_seq: Option<u64>,
+}
+
+/// Pushes a `Block` message with the header of every block, from
+/// `from_height` or the next block on.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubscribeBlocks {
+    #[serde(default)]
+    pub from_height: Option<u64>,
+}
+
+/// Ends a subscription of any kind.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Unsubscribe {
+    /// id returned by `Subscribe`
+    pub subscription: u64,
+}
+
+/// Keys that made the most commits abort, e.g. hot game state.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetContentionReport {
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// clear the counters after reporting
+    #[serde(default)]
+    pub reset: bool,
+}
+
+/// Writes the latest committed state to a snapshot file on the node, at
+/// `path` inside its snapshot dir.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportSnapshot {
+    pub path: String,
+}
+
+/// Seeds an empty node from the snapshot file at `path` inside its
+/// snapshot dir.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImportSnapshot {
+    pub path: String,
+}
+
+/// Bytes taken by the objects, log, blocks and checkpoints in the data dir.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetDiskUsage {}
+
+/// Deploys the Bend `source` under a new `code_id`, kept in the data dir
+/// across restarts.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeployCode {
+    pub code_id: String,
+    pub source: String,
+}
+
+/// Lets the connection send admin messages if `token` is the node's admin
+/// token.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Authenticate {
+    pub token: String,
+}
+
+/// Stops taking transactions, which are answered `busy` until `Resume`.
+/// Queries are still served.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Pause {}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Resume {}
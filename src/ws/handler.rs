@@ -0,0 +1,309 @@
+use super::events::{
+    ApiError, DeployCode, ErrorCode, ExportSnapshot, GetBlock, GetContentionReport, GetTxStatus,
+    GetValueAt, GetValues, ImportSnapshot, Message, Simulate, SubmitBatch, SubmitTx,
+};
+use super::Node;
+use crate::block_stm::key_range::KeyRange;
+use crate::block_stm::{get_val, get_val_at, get_vals, scan_vals};
+use crate::executor::simulate_tx;
+use crate::storage::{
+    disk_usage,
+    export::{export_snapshot, import_snapshot, snapshot_path},
+};
+use crate::svm::codes::save_code;
+use log::info;
+use serde_json::{json, Value};
+use std::{fs, io, path::PathBuf, sync::atomic::Ordering};
+
+const DEFAULT_SCAN_LIMIT: usize = 100;
+const DEFAULT_CONTENTION_REPORT_LIMIT: usize = 20;
+
+/// Answers one message. The result is the response body, whichever framing
+/// the message came in. Callers check that admin messages come from an
+/// admin.
+pub async fn handle(node: &Node, message: Message) -> Result<Value, ApiError> {
+    let tm = node.tm.clone();
+    match message {
+        Message::SubmitTx(SubmitTx { tx_body }) => {
+            node.check_taking_txs(1)?;
+            let tx_result = node.blocks.submit(tx_body).await?;
+            Ok(serde_json::to_value(tx_result).unwrap())
+        }
+        Message::SubmitBatch(SubmitBatch { txs, mode }) => {
+            node.check_taking_txs(txs.len())?;
+            let results = node.blocks.submit_batch(txs, mode).await?;
+            Ok(json!({ "results": results }))
+        }
+        Message::Simulate(Simulate { tx_body }) => {
+            if !node.svm.has_code(&tx_body.code_hash) {
+                return Err(ApiError::new(
+                    ErrorCode::NotFound,
+                    format!("code id={} is not deployed", tx_body.code_hash),
+                ));
+            }
+            let svm = node.svm.clone();
+            let receipt = compute(node, move || Ok(simulate_tx(&tx_body, tm, svm))).await?;
+            Ok(serde_json::to_value(receipt).unwrap())
+        }
+        Message::GetBlock(GetBlock { height }) => Ok(json!({
+            "height": height,
+            "block": node.blocks.store().get(height)
+        })),
+        Message::GetLatestBlock(_) => Ok(json!({ "block": node.blocks.store().latest() })),
+        Message::GetTxStatus(GetTxStatus { tx_hash }) => {
+            let (status, receipt) = node.blocks.tx_status(&tx_hash);
+            Ok(json!({
+                "tx_hash": tx_hash,
+                "status": status,
+                "block_height": receipt.as_ref().map(|(height, _)| height),
+                "receipt": receipt.map(|(_, receipt)| receipt)
+            }))
+        }
+        Message::GetValueAt(GetValueAt { addr, at, prove }) => match at {
+            None if prove => {
+                let (object, state_root, proof) = tm.get_with_proof(addr.as_bytes());
+                Ok(json!({
+                    "addr": addr,
+                    "value": object.as_ref().map(|obj| &obj.value),
+                    "version": object.as_ref().map(|obj| obj.version),
+                    "state_root": hex::encode(state_root),
+                    "proof": proof
+                }))
+            }
+            None => Ok(json!({
+                "addr": addr,
+                "value": get_val(tm, addr.clone())
+            })),
+            Some(_) if prove => Err(ApiError::new(
+                ErrorCode::InvalidArgument,
+                "proofs are only served for the latest state",
+            )),
+            Some(at) => {
+                let value = get_val_at(tm, addr.clone(), at)
+                    .map_err(|e| ApiError::new(ErrorCode::InvalidArgument, e))?;
+                Ok(json!({ "addr": addr, "at": at, "value": value }))
+            }
+        },
+        Message::GetValues(GetValues { addrs, at }) => {
+            let (seq, values) = get_vals(tm, &addrs, at)
+                .map_err(|e| ApiError::new(ErrorCode::InvalidArgument, e))?;
+            Ok(json!({
+                "at": seq,
+                "values": addrs
+                    .iter()
+                    .zip(values)
+                    .map(|(addr, value)| json!({ "addr": addr, "value": value }))
+                    .collect::<Vec<_>>()
+            }))
+        }
+        Message::ScanKeys(scan) => {
+            let range = match scan.prefix {
+                Some(prefix) => KeyRange::prefix(prefix.as_bytes()),
+                None => KeyRange::between(
+                    scan.start.map(String::into_bytes),
+                    scan.end.map(String::into_bytes),
+                ),
+            };
+            let cursor = scan.cursor.map(String::into_bytes);
+            let limit = scan.limit.unwrap_or(DEFAULT_SCAN_LIMIT);
+            let page = scan_vals(tm, &range, cursor.as_deref(), limit, scan.at)
+                .map_err(|e| ApiError::new(ErrorCode::InvalidArgument, e))?;
+            Ok(json!({
+                "at": page.seq,
+                "entries": page
+                    .entries
+                    .iter()
+                    .map(|(key, object)| json!({
+                        "addr": String::from_utf8_lossy(key),
+                        "value": object.value,
+                        "version": object.version
+                    }))
+                    .collect::<Vec<_>>(),
+                "next_cursor": page
+                    .next_cursor
+                    .map(|cursor| String::from_utf8_lossy(&cursor).into_owned())
+            }))
+        }
+        Message::GetContentionReport(GetContentionReport { limit, reset }) => {
+            let limit = limit.unwrap_or(DEFAULT_CONTENTION_REPORT_LIMIT);
+            let contention = tm.contention();
+            let report = json!({
+                "contended_keys": contention
+                    .top(limit)
+                    .into_iter()
+                    .map(|(key, stats)| json!({
+                        "addr": String::from_utf8_lossy(&key),
+                        "stats": stats
+                    }))
+                    .collect::<Vec<_>>()
+            });
+            if reset {
+                contention.reset();
+            }
+            Ok(report)
+        }
+        Message::ExportSnapshot(ExportSnapshot { path }) => {
+            let file = node_snapshot_path(node, &path)?;
+            let header = blocking(move || {
+                if let Some(parent) = file.parent() {
+                    fs::create_dir_all(parent).map_err(io_error)?;
+                }
+                export_snapshot(&tm, &file).map_err(io_error)
+            })
+            .await?;
+            Ok(json!({ "path": path, "snapshot": header }))
+        }
+        Message::ImportSnapshot(ImportSnapshot { path }) => {
+            let file = node_snapshot_path(node, &path)?;
+            let header = blocking(move || import_snapshot(&tm, &file).map_err(io_error)).await?;
+            Ok(json!({ "path": path, "snapshot": header }))
+        }
+        Message::GetDiskUsage(_) => {
+            let Some(dir) = node.data_dir.clone() else {
+                return Err(ApiError::new(
+                    ErrorCode::NotFound,
+                    "node runs without a data dir",
+                ));
+            };
+            let usage = disk_usage(&dir).map_err(io_error)?;
+            Ok(json!({ "data_dir": dir, "usage": usage }))
+        }
+        Message::DeployCode(DeployCode { code_id, source }) => {
+            let (svm, data_dir) = (node.svm.clone(), node.data_dir.clone());
+            let id = code_id.clone();
+            compute(node, move || {
+                svm.deploy(&id, &source, || match &data_dir {
+                    Some(dir) => save_code(dir, &id, &source),
+                    None => Ok(()),
+                })
+                .map_err(io_error)
+            })
+            .await?;
+            info!("deployed code id={}", code_id);
+            Ok(json!({ "code_id": code_id }))
+        }
+        Message::Subscribe(_)
+        | Message::SubscribeTxs(_)
+        | Message::SubscribeBlocks(_)
+        | Message::Unsubscribe(_) => Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            "subscriptions are only served over a WebSocket connection",
+        )),
+        Message::Authenticate(_) => Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            "authenticate over a WebSocket connection",
+        )),
+        Message::Pause(_) => {
+            node.paused.store(true, Ordering::Relaxed);
+            info!("paused, taking no transactions");
+            Ok(json!({ "paused": true }))
+        }
+        Message::Resume(_) => {
+            node.paused.store(false, Ordering::Relaxed);
+            info!("resumed, taking transactions");
+            Ok(json!({ "paused": false }))
+        }
+        Message::ReallocateMemory(_) => {
+            let genesis = node.genesis.clone();
+            // between blocks, so no block's state root has part of it
+            let _window = node.blocks.outside_blocks().await;
+            let objects = blocking(move || {
+                genesis
+                    .apply(&tm)
+                    .map_err(|e| ApiError::new(ErrorCode::Internal, e))
+            })
+            .await?;
+            info!("reallocated memory objects={}", objects);
+            Ok(json!({ "objects": objects }))
+        }
+    }
+}
+
+/// Runs `f` on the execution engine, for work that runs or parses code.
+async fn compute<T, F>(node: &Node, f: F) -> Result<T, ApiError>
+where
+    F: FnOnce() -> Result<T, ApiError> + Send + 'static,
+    T: Send + 'static,
+{
+    node.engine
+        .try_run(f)
+        .await?
+        .map_err(|e| ApiError::new(ErrorCode::Internal, e))?
+}
+
+/// Runs `f` on the blocking pool, for work that reads or writes whole files
+/// or the whole state.
+/// The file `path` names inside the node's snapshot dir.
+fn node_snapshot_path(node: &Node, path: &str) -> Result<PathBuf, ApiError> {
+    let Some(dir) = &node.snapshot_dir else {
+        return Err(ApiError::new(
+            ErrorCode::NotFound,
+            "node runs without a snapshot dir",
+        ));
+    };
+    snapshot_path(dir, path).map_err(io_error)
+}
+
+async fn blocking<T, F>(f: F) -> Result<T, ApiError>
+where
+    F: FnOnce() -> Result<T, ApiError> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| ApiError::new(ErrorCode::Internal, e.to_string()))?
+}
+
+fn io_error(e: io::Error) -> ApiError {
+    let code = match e.kind() {
+        io::ErrorKind::NotFound => ErrorCode::NotFound,
+        io::ErrorKind::AlreadyExists | io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => {
+            ErrorCode::InvalidArgument
+        }
+        _ => ErrorCode::Internal,
+    };
+    ApiError::new(code, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_stm::svm_memory::SVMMemory;
+    use crate::executor::engine::EngineConfig;
+    use crate::svm::{object::SVMObject, primitive_types::SVMPrimitives};
+    use crate::test_support::test_node;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn snapshots_are_imported_from_the_snapshot_dir_into_empty_nodes() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = SVMMemory::new();
+        let object = SVMObject {
+            value: SVMPrimitives::U24(7),
+            version: 1,
+        };
+        source.set(b"0x1".to_vec(), object).unwrap();
+        export_snapshot(&source, &dir.path().join("state.snapshot")).unwrap();
+
+        let tm = Arc::new(SVMMemory::new());
+        let node = Node {
+            snapshot_dir: Some(dir.path().to_path_buf()),
+            ..test_node(tm.clone(), EngineConfig::default(), None)
+        };
+        let import = |path: &str| {
+            Message::ImportSnapshot(ImportSnapshot {
+                path: path.to_string(),
+            })
+        };
+        let outside = handle(&node, import("../state.snapshot")).await;
+        assert_eq!(outside.unwrap_err().code, ErrorCode::InvalidArgument);
+        assert!(tm.is_empty());
+
+        let imported = handle(&node, import("state.snapshot")).await.unwrap();
+        assert_eq!(imported["snapshot"]["objects"], 1);
+        assert_eq!(tm.state_root(), source.state_root());
+
+        let again = handle(&node, import("state.snapshot")).await;
+        assert_eq!(again.unwrap_err().code, ErrorCode::InvalidArgument);
+    }
+}
@@ -0,0 +1,326 @@
+use super::events::{ApiError, ErrorCode};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// How much a client may ask of the node. Requests over a limit are
+/// answered at once with a `busy` error.
+#[derive(Clone, Debug)]
+pub struct LimitConfig {
+    /// largest message a client may send, in bytes
+    pub max_message_bytes: usize,
+    /// messages one connection may send a second
+    pub connection_rate: u32,
+    /// messages all connections together may send a second
+    pub global_rate: u32,
+    /// requests one connection may have in flight, counting each
+    /// transaction of a batch
+    pub connection_in_flight: usize,
+    /// requests all connections together may have in flight
+    pub global_in_flight: usize,
+    /// subscriptions one connection may hold
+    pub connection_subscriptions: usize,
+    /// subscriptions all connections together may hold
+    pub global_subscriptions: usize,
+    /// JSON-RPC connections served at once, more are turned away
+    pub rpc_connections: usize,
+    /// how long a JSON-RPC client may take to send a request, or stay idle
+    /// between requests, before its connection is closed
+    pub rpc_idle_timeout: Duration,
+}
+
+impl Default for LimitConfig {
+    fn default() -> Self {
+        Self {
+            max_message_bytes: 4 << 20,
+            connection_rate: 500,
+            global_rate: 20_000,
+            connection_in_flight: 1_000,
+            global_in_flight: 20_000,
+            connection_subscriptions: 100,
+            global_subscriptions: 10_000,
+            rpc_connections: 1_024,
+            rpc_idle_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Lets `rate` messages through a second, in bursts of up to a second's
+/// worth.
+struct RateLimit {
+    rate: f64,
+    /// tokens left and when they were counted
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimit {
+    fn new(rate: u32) -> Self {
+        let rate = f64::from(rate.max(1));
+        Self {
+            rate,
+            bucket: Mutex::new((rate, Instant::now())),
+        }
+    }
+
+    fn try_take(&self) -> bool {
+        let mut bucket = self.bucket.lock().unwrap();
+        let (tokens, counted) = &mut *bucket;
+        let now = Instant::now();
+        *tokens = (*tokens + now.duration_since(*counted).as_secs_f64() * self.rate).min(self.rate);
+        *counted = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+
+    /// Returns a token taken for a message that was refused after all.
+    fn give_back(&self) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.0 = (bucket.0 + 1.0).min(self.rate);
+    }
+}
+
+/// Requests in flight, up to a capacity.
+struct InFlight {
+    permits: Arc<Semaphore>,
+    capacity: usize,
+}
+
+impl InFlight {
+    fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            permits: Arc::new(Semaphore::new(capacity)),
+            capacity,
+        }
+    }
+
+    /// A batch bigger than the whole capacity takes all of it.
+    fn try_take(&self, weight: usize) -> Option<OwnedSemaphorePermit> {
+        let weight = weight.clamp(1, self.capacity) as u32;
+        self.permits.clone().try_acquire_many_owned(weight).ok()
+    }
+}
+
+/// The node-wide limits, shared by every connection.
+pub struct Limits {
+    config: LimitConfig,
+    rate: RateLimit,
+    in_flight: InFlight,
+    subscriptions: InFlight,
+    /// limits of the peers with a connection open
+    peers: Mutex<HashMap<IpAddr, Weak<ConnectionLimits>>>,
+}
+
+impl Limits {
+    pub fn new(config: LimitConfig) -> Self {
+        Self {
+            rate: RateLimit::new(config.global_rate),
+            in_flight: InFlight::new(config.global_in_flight),
+            subscriptions: InFlight::new(config.global_subscriptions),
+            peers: Mutex::new(HashMap::new()),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &LimitConfig {
+        &self.config
+    }
+
+    /// Limits of a new connection, within the node-wide ones.
+    pub fn connection(self: &Arc<Self>) -> ConnectionLimits {
+        ConnectionLimits {
+            global: self.clone(),
+            rate: RateLimit::new(self.config.connection_rate),
+            in_flight: InFlight::new(self.config.connection_in_flight),
+        }
+    }
+
+    /// Limits shared by every connection from `ip`, for clients that open a
+    /// connection per request. They are dropped with the peer's last
+    /// connection.
+    pub fn peer(self: &Arc<Self>, ip: IpAddr) -> Arc<ConnectionLimits> {
+        let mut peers = self.peers.lock().unwrap();
+        if let Some(limits) = peers.get(&ip).and_then(Weak::upgrade) {
+            return limits;
+        }
+        peers.retain(|_, limits| limits.strong_count() > 0);
+        let limits = Arc::new(self.connection());
+        peers.insert(ip, Arc::downgrade(&limits));
+        limits
+    }
+
+    /// Admits a request of `weight` against the node-wide limits only, for
+    /// requests not tied to a connection.
+    pub fn admit(&self, weight: usize) -> Result<Admitted, ApiError> {
+        let global = self.in_flight.try_take(weight).ok_or_else(|| {
+            busy(format!(
+                "node has {} requests in flight",
+                self.config.global_in_flight
+            ))
+        })?;
+        if !self.rate.try_take() {
+            return Err(busy(format!(
+                "node receives more than {} messages a second",
+                self.config.global_rate
+            )));
+        }
+        Ok(Admitted {
+            _connection: None,
+            _global: global,
+        })
+    }
+}
+
+/// The limits of one connection.
+pub struct ConnectionLimits {
+    global: Arc<Limits>,
+    rate: RateLimit,
+    in_flight: InFlight,
+}
+
+impl ConnectionLimits {
+    /// Admits a request of `weight`, the number of transactions it submits
+    /// or 1, until the returned value is dropped. Only admitted requests
+    /// count against the rates.
+    pub fn admit(&self, weight: usize) -> Result<Admitted, ApiError> {
+        let config = &self.global.config;
+        let connection = self.in_flight.try_take(weight).ok_or_else(|| {
+            busy(format!(
+                "connection has {} requests in flight",
+                config.connection_in_flight
+            ))
+        })?;
+        if !self.rate.try_take() {
+            return Err(busy(format!(
+                "connection sends more than {} messages a second",
+                config.connection_rate
+            )));
+        }
+        let mut admitted = self
+            .global
+            .admit(weight)
+            .inspect_err(|_| self.rate.give_back())?;
+        admitted._connection = Some(connection);
+        Ok(admitted)
+    }
+
+    /// Room for one more subscription of a connection already holding
+    /// `held`, until the returned permit is dropped.
+    pub fn subscribe(&self, held: usize) -> Result<OwnedSemaphorePermit, ApiError> {
+        let config = &self.global.config;
+        if held >= config.connection_subscriptions {
+            return Err(busy(format!(
+                "connection holds {} subscriptions",
+                config.connection_subscriptions
+            )));
+        }
+        self.global.subscriptions.try_take(1).ok_or_else(|| {
+            busy(format!(
+                "node holds {} subscriptions",
+                config.global_subscriptions
+            ))
+        })
+    }
+}
+
+/// Room taken by a request in flight, given back when dropped.
+#[derive(Debug)]
+pub struct Admitted {
+    _connection: Option<OwnedSemaphorePermit>,
+    _global: OwnedSemaphorePermit,
+}
+
+fn busy(message: String) -> ApiError {
+    ApiError::new(ErrorCode::Busy, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_limits(global_rate: u32) -> Arc<Limits> {
+        Arc::new(Limits::new(LimitConfig {
+            connection_rate: 3,
+            global_rate,
+            connection_in_flight: 4,
+            global_in_flight: 6,
+            ..LimitConfig::default()
+        }))
+    }
+
+    #[test]
+    fn connections_are_limited_alone_and_together() {
+        let limits = node_limits(100);
+        let (first, second) = (limits.connection(), limits.connection());
+        let batch = first.admit(3).unwrap();
+        let query = first.admit(1).unwrap();
+        let e = first.admit(1).unwrap_err();
+        assert_eq!(e.code, ErrorCode::Busy);
+        assert!(e.message.starts_with("connection has 4 requests in flight"));
+        // refused without using up one of its 3 messages of this second
+        drop(query);
+        let query = first.admit(1).unwrap();
+
+        let others = second.admit(2).unwrap();
+        let e = second.admit(1).unwrap_err();
+        assert!(e.message.starts_with("node has 6 requests in flight"));
+        drop((batch, query, others));
+        assert!(first.admit(1).unwrap_err().message.contains("a second"));
+        assert!(second.admit(1).is_ok());
+
+        let limits = node_limits(5);
+        let (first, second) = (limits.connection(), limits.connection());
+        // a batch bigger than the limits still runs, alone
+        assert!(first.admit(100).is_ok());
+        assert!(first.admit(1).is_ok());
+        assert!(first.admit(1).is_ok());
+        assert!(second.admit(1).is_ok());
+        assert!(second.admit(1).is_ok());
+        let e = second.admit(1).unwrap_err();
+        assert!(e.message.starts_with("node receives more than 5"));
+    }
+
+    #[test]
+    fn subscriptions_and_peers_are_limited() {
+        let limits = Arc::new(Limits::new(LimitConfig {
+            connection_subscriptions: 2,
+            global_subscriptions: 3,
+            ..LimitConfig::default()
+        }));
+        let (first, second) = (limits.connection(), limits.connection());
+        let held = vec![first.subscribe(0).unwrap(), first.subscribe(1).unwrap()];
+        let e = first.subscribe(2).unwrap_err();
+        assert_eq!(e.code, ErrorCode::Busy);
+        assert!(e.message.starts_with("connection holds 2 subscriptions"));
+        let other = second.subscribe(0).unwrap();
+        assert!(second
+            .subscribe(1)
+            .unwrap_err()
+            .message
+            .starts_with("node holds 3"));
+        drop(held);
+        assert!(second.subscribe(1).is_ok());
+        drop(other);
+
+        // connections of one peer share its limits until the last one closes
+        let limits = node_limits(100);
+        let ip: IpAddr = [127, 0, 0, 1].into();
+        let (a, b) = (limits.peer(ip), limits.peer(ip));
+        let _taken = (
+            a.admit(1).unwrap(),
+            a.admit(1).unwrap(),
+            b.admit(1).unwrap(),
+        );
+        assert!(b.admit(1).unwrap_err().message.contains("a second"));
+        assert!(limits.peer([127, 0, 0, 2].into()).admit(1).is_ok());
+        drop((a, b));
+        assert!(limits.peer(ip).admit(1).is_ok());
+    }
+}
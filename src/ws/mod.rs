@@ -1,36 +1,134 @@
-use crate::block_stm::get_val;
-use crate::block_stm::svm_memory::{retry_transaction, SVMMemory};
-use crate::examples::alloc::{self};
-use crate::executor::process_tx;
-use crate::executor::types::TxResult;
-use crate::svm::{primitive_types::SVMPrimitives, svm::SVM};
-use events::{GetValueAt, Message, SubmitTx};
-use futures::lock::Mutex;
-use futures::{SinkExt, StreamExt};
-use log::{error, info};
-use serde_json::json;
-use std::sync::Arc;
-use tokio::net::{TcpListener, TcpStream};
-use tokio_tungstenite::{accept_async, WebSocketStream};
+use crate::block::builder::BlockProducer;
+use crate::block_stm::svm_memory::SVMMemory;
+use crate::executor::engine::ExecutionEngine;
+use crate::genesis::Genesis;
+use crate::svm::svm::SVM;
+use auth::{unauthorized, AdminAuth};
+use binary::{BinaryRequest, Encoding, BINARY_PROTOCOL};
+use events::{
+    ApiError, Authenticate, ErrorCode, Message, Request, Response, SubmitBatch, SubmitTx,
+    Unsubscribe,
+};
+use futures::StreamExt;
+use handler::handle;
+use limits::Limits;
+use log::{debug, error, info, warn};
+use serde_json::{json, Value};
+use session::{Replied, Session};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::watch,
+};
+use tokio_tungstenite::{
+    accept_hdr_async_with_config,
+    tungstenite::{
+        handshake::server::{Request as HandshakeRequest, Response as HandshakeResponse},
+        http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue},
+        protocol::{frame::coding::CloseCode, CloseFrame, WebSocketConfig},
+        Error as WsError, Message as WsMessage,
+    },
+    WebSocketStream,
+};
 
+pub mod auth;
+pub mod binary;
 pub mod events;
+pub mod handler;
+pub mod limits;
+pub mod session;
 
-pub async fn run_ws(addr: &str, tm: Arc<SVMMemory>, svm: Arc<SVM>) {
-    alloc::alloc_incremental(tm.clone(), 0, 1_000_000).await;
-    alloc::alloc_duangua(tm.clone(), 1_000_001, 1_000_002).await;
+/// Everything requests are served from, shared by all connections.
+pub struct Node {
+    pub tm: Arc<SVMMemory>,
+    pub svm: Arc<SVM>,
+    /// where transactions and other CPU-heavy requests run
+    pub engine: Arc<ExecutionEngine>,
+    pub blocks: Arc<BlockProducer>,
+    pub genesis: Arc<Genesis>,
+    /// data dir of the durable storage, if the node has one
+    pub data_dir: Option<PathBuf>,
+    /// where `ExportSnapshot` writes and `ImportSnapshot` reads, both are
+    /// refused without one
+    pub snapshot_dir: Option<PathBuf>,
+    /// how much clients may ask of the node
+    pub limits: Arc<Limits>,
+    /// who may send admin messages
+    pub admin: AdminAuth,
+    /// set by `Pause`, while no transactions are taken
+    pub paused: AtomicBool,
+}
 
+impl Node {
+    /// Fails while the node is paused, and for batches bigger than the
+    /// engine's queue, which would never find room.
+    pub fn check_taking_txs(&self, txs: usize) -> Result<(), ApiError> {
+        if self.paused.load(Ordering::Relaxed) {
+            return Err(ApiError::new(
+                ErrorCode::Busy,
+                "node is paused and takes no transactions",
+            ));
+        }
+        if txs > self.engine.capacity() {
+            return Err(ApiError::new(
+                ErrorCode::InvalidArgument,
+                format!(
+                    "batch of {} txs is over the execution queue capacity={}",
+                    txs,
+                    self.engine.capacity()
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub async fn run_ws(addr: &str, node: Arc<Node>) {
     let listener = TcpListener::bind(&addr).await.expect("Failed to bind");
     info!("web socket is running on: {}", addr);
+    serve(listener, node).await;
+}
 
-    while let Ok((stream, _)) = listener.accept().await {
-        let tm = tm.clone();
-        let svm = svm.clone();
+/// Accepts WebSocket connections on `listener`.
+pub async fn serve(listener: TcpListener, node: Arc<Node>) {
+    while let Ok((stream, peer)) = listener.accept().await {
+        let node = node.clone();
 
         tokio::spawn(async move {
-            match accept_async(stream).await {
+            let mut encoding = Encoding::Json;
+            // the signature tungstenite calls back with
+            #[allow(clippy::result_large_err)]
+            let negotiate = |request: &HandshakeRequest, mut response: HandshakeResponse| {
+                let offered = request
+                    .headers()
+                    .get(SEC_WEBSOCKET_PROTOCOL)
+                    .and_then(|offered| offered.to_str().ok());
+                encoding = Encoding::negotiate(offered);
+                if encoding == Encoding::Binary {
+                    response.headers_mut().insert(
+                        SEC_WEBSOCKET_PROTOCOL,
+                        HeaderValue::from_static(BINARY_PROTOCOL),
+                    );
+                }
+                Ok(response)
+            };
+            let max_message_bytes = node.limits.config().max_message_bytes;
+            let config = WebSocketConfig {
+                max_message_size: Some(max_message_bytes),
+                max_frame_size: Some(max_message_bytes),
+                ..WebSocketConfig::default()
+            };
+            match accept_hdr_async_with_config(stream, negotiate, Some(config)).await {
                 Ok(stream) => {
-                    info!("connecct");
-                    tokio::spawn(handle_connection(stream, tm, svm));
+                    info!("connecct encoding={:?}", encoding);
+                    tokio::spawn(handle_connection(stream, node, encoding, peer));
                 }
                 Err(e) => {
                     error!("Error during the websocket handshake occurred: {}", e);
@@ -42,88 +140,269 @@ pub async fn run_ws(addr: &str, tm: Arc<SVMMemory>, svm: Arc<SVM>) {
 
 async fn handle_connection(
     ws_stream: WebSocketStream<TcpStream>,
-    tm: Arc<SVMMemory>,
-    svm: Arc<SVM>,
+    node: Arc<Node>,
+    encoding: Encoding,
+    peer: SocketAddr,
 ) {
     let (write, mut read) = ws_stream.split();
-    let ws_send = Arc::new(Mutex::new(write));
+    let session = Arc::new(Session::new(
+        write,
+        encoding,
+        peer,
+        node.limits.connection(),
+    ));
 
-    while let Some(Ok(msg)) = read.next().await {
-        let send_clone = Arc::clone(&ws_send);
-        let tm_loop = Arc::clone(&tm);
-        let svm_loop = Arc::clone(&svm);
-        tokio::spawn(async move {
-            if msg.is_text() || msg.is_binary() {
-                let text = msg.clone().into_text().unwrap();
-
-                let message = match serde_json::from_str::<Message>(&text) {
-                    Ok(message) => message,
-                    Err(_) => {
-                        let mut send = send_clone.lock().await;
-                        _ = send
-                            .send(format!("VM does not support message: {}", text).into())
-                            .await;
-                        return ();
-                    }
+    loop {
+        let msg = match read.next().await {
+            Some(Ok(msg)) => msg,
+            Some(Err(WsError::Capacity(e))) => {
+                warn!("closing ws connection err={}", e);
+                let close = CloseFrame {
+                    code: CloseCode::Size,
+                    reason: e.to_string().into(),
                 };
+                session.send(WsMessage::Close(Some(close))).await;
+                break;
+            }
+            _ => break,
+        };
+        let incoming = match msg {
+            WsMessage::Binary(bytes) if encoding == Encoding::Binary => decode_binary(&bytes),
+            WsMessage::Text(_) | WsMessage::Binary(_) => match msg.into_text() {
+                Ok(text) => decode(&text),
+                Err(_) => Incoming::Unsupported(String::new()),
+            },
+            _ => continue,
+        };
+        // turned away at once; replies to a client that does not read them
+        // hold up reading its next messages
+        let admitted = match session.limits().admit(incoming.weight()) {
+            Ok(admitted) => admitted,
+            Err(e) => {
+                debug!("ws request refused err={}", e.message);
+                session.send(incoming.refusal(&session, e)).await;
+                continue;
+            }
+        };
 
-                info!("Received message: {:?}", message);
-                match message {
-                    Message::SubmitTx(SubmitTx { tx_body }) => {
-                        let mut send = send_clone.lock().await;
-                        let tx_result = match process_tx(tx_body.clone(), tm_loop, svm_loop) {
-                            Ok(ret_val) => TxResult {
-                                code_hash: tx_body.code_hash,
-                                tx_hash: tx_body.tx_hash,
-                                ret_value: Some(ret_val),
-                                status: true,
-                                errs: None,
-                            },
-                            Err(e) => TxResult {
-                                tx_hash: tx_body.tx_hash,
-                                code_hash: tx_body.code_hash,
-                                status: false,
-                                ret_value: None,
-                                errs: Some(e.clone().into()),
-                            },
-                        };
-                        let json_tx_result = serde_json::to_string(&tx_result).unwrap();
-                        _ = send.send(json_tx_result.into()).await;
-                    }
-                    Message::GetValueAt(GetValueAt { addr }) => {
-                        tokio::spawn(async move {
-                            let mut send = send_clone.lock().await;
-                            let result = get_val(tm_loop, addr.clone());
-                            // transform to confirmed transaction
-                            let query_result = json!({
-                                "addr": addr,
-                                "value": result
-                            });
-                            if let Err(e) = send.send(query_result.to_string().into()).await {
-                                println!("failed to send query balance result: {}", e);
-                            }
-                        });
-                    }
-                    Message::ReallocateMemory(_) => {
-                        tokio::spawn(async move {
-                            alloc::alloc_incremental(tm_loop.clone(), 0, 1_000_000).await;
-                            alloc::alloc_duangua(tm_loop.clone(), 1_000_001, 1_000_002).await;
-                            info!("reallocated memory");
-                        });
-                    }
+        let session_loop = Arc::clone(&session);
+        let node_loop = Arc::clone(&node);
+        tokio::spawn(async move {
+            let (queued, replied) = watch::channel(());
+            let reply = match incoming {
+                Incoming::Request(id, kind, message) => {
+                    let result = match message {
+                        Ok(message) => {
+                            info!("Received message id={} {:?}", id, message);
+                            answer(&node_loop, &session_loop, &id, message, &replied).await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    session_loop.encode(Response::new(Some(id), kind, result))
+                }
+                Incoming::Invalid(response) => session_loop.encode(response),
+                Incoming::Legacy(message) => {
+                    info!("Received message: {:?}", message);
+                    let reply = match dispatch(&node_loop, &session_loop, message, &replied).await {
+                        Ok(result) => result.to_string(),
+                        Err(e) => json!({ "error": e }).to_string(),
+                    };
+                    WsMessage::Text(reply)
+                }
+                Incoming::Unsupported(text) => {
+                    WsMessage::Text(format!("VM does not support message: {}", text))
                 }
+            };
+            drop(admitted);
+
+            if !session_loop.send(reply).await {
+                error!("failed to send reply: connection closed");
             }
+            _ = queued.send(());
         });
     }
 
+    session.close(&node.tm);
     info!("ws disconnected");
 }
 
+/// Answers `message`, serving the ones tied to this connection itself.
+/// Admin messages are refused until the connection authenticates.
+async fn dispatch(
+    node: &Node,
+    session: &Arc<Session>,
+    message: Message,
+    replied: &Replied,
+) -> Result<Value, ApiError> {
+    if message.is_admin() && !session.is_admin() {
+        warn!(
+            "refused unauthenticated admin message type={} peer={}",
+            message.kind(),
+            session.peer()
+        );
+        return Err(unauthorized(message.kind()));
+    }
+    match message {
+        Message::Authenticate(Authenticate { token }) => {
+            if !node.admin.check(&token) {
+                warn!("admin authentication failed peer={}", session.peer());
+                return Err(ApiError::new(
+                    ErrorCode::Unauthorized,
+                    "not the admin token",
+                ));
+            }
+            session.grant_admin();
+            info!("admin authenticated peer={}", session.peer());
+            Ok(json!({ "admin": true }))
+        }
+        Message::Subscribe(subscribe) => session.subscribe(&node.tm, subscribe, replied),
+        Message::SubscribeTxs(subscribe) => {
+            session.subscribe_txs(node.blocks.store(), subscribe, replied)
+        }
+        Message::SubscribeBlocks(subscribe) => {
+            session.subscribe_blocks(node.blocks.store(), subscribe, replied)
+        }
+        Message::Unsubscribe(Unsubscribe { subscription }) => {
+            session.unsubscribe(&node.tm, subscription)
+        }
+        message => handle(node, message).await,
+    }
+}
+
+/// Answers an envelope. Submitted transactions are acknowledged with an
+/// `Accepted` response under the same `id`, carrying their hashes, as soon
+/// as they are queued; the response with their results follows.
+async fn answer(
+    node: &Node,
+    session: &Arc<Session>,
+    id: &str,
+    message: Message,
+    replied: &Replied,
+) -> Result<Value, ApiError> {
+    let (txs, mode) = match message {
+        Message::SubmitTx(SubmitTx { tx_body }) => (vec![tx_body], None),
+        Message::SubmitBatch(SubmitBatch { txs, mode }) => (txs, Some(mode)),
+        message => return dispatch(node, session, message, replied).await,
+    };
+    node.check_taking_txs(txs.len())?;
+    let queued = node.blocks.enqueue(txs, mode.unwrap_or_default())?;
+    let tx_hashes: Vec<&str> = queued
+        .txs()
+        .iter()
+        .map(|tx_body| tx_body.tx_hash.as_str())
+        .collect();
+    let accepted = Response::new(
+        Some(id.to_string()),
+        "Accepted".to_string(),
+        Ok(json!({ "tx_hashes": tx_hashes })),
+    );
+    session.send(session.encode(accepted)).await;
+
+    let mut results = queued.results().await;
+    Ok(match mode {
+        None => serde_json::to_value(results.remove(0)).unwrap(),
+        Some(_) => json!({ "results": results }),
+    })
+}
+
+/// A message read off the socket.
+enum Incoming {
+    /// an envelope: its id, type and message
+    Request(String, String, Result<Message, ApiError>),
+    /// has a `v`, but is not a valid envelope; answered with an error
+    Invalid(Response),
+    Legacy(Message),
+    Unsupported(String),
+}
+
+impl Incoming {
+    /// How much of the limits answering it takes: a unit per submitted
+    /// transaction, one for anything else.
+    fn weight(&self) -> usize {
+        match self {
+            Incoming::Request(_, _, Ok(Message::SubmitBatch(SubmitBatch { txs, .. })))
+            | Incoming::Legacy(Message::SubmitBatch(SubmitBatch { txs, .. })) => txs.len(),
+            _ => 1,
+        }
+    }
+
+    /// The reply when it is turned away with `e`.
+    fn refusal(self, session: &Session, e: ApiError) -> WsMessage {
+        match self {
+            Incoming::Request(id, kind, _) => session.encode(Response::new(Some(id), kind, Err(e))),
+            Incoming::Invalid(response) => {
+                session.encode(Response::new(response.id, response.kind, Err(e)))
+            }
+            Incoming::Legacy(_) | Incoming::Unsupported(_) => {
+                WsMessage::Text(json!({ "error": e }).to_string())
+            }
+        }
+    }
+}
+
+fn decode(text: &str) -> Incoming {
+    match serde_json::from_str::<Value>(text) {
+        Ok(value) if value.get("v").is_some() => {
+            let id = value.get("id").and_then(Value::as_str).map(str::to_string);
+            let kind = value
+                .get("type")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            match serde_json::from_value::<Request>(value.clone()) {
+                Ok(request) => {
+                    let message = request.message();
+                    Incoming::Request(request.id, request.kind, message)
+                }
+                Err(e) => Incoming::Invalid(Response::new(
+                    id,
+                    kind.to_string(),
+                    Err(ApiError::new(ErrorCode::InvalidRequest, e.to_string())),
+                )),
+            }
+        }
+        _ => match serde_json::from_str::<Message>(text) {
+            Ok(message) => Incoming::Legacy(message),
+            Err(_) => Incoming::Unsupported(text.to_string()),
+        },
+    }
+}
+
+/// Reads a binary frame of a connection that negotiated `BINARY_PROTOCOL`.
+fn decode_binary(bytes: &[u8]) -> Incoming {
+    match BinaryRequest::decode(bytes) {
+        Ok(request) => {
+            let (id, kind) = (request.id.clone(), request.message.kind().to_string());
+            Incoming::Request(id, kind, request.message())
+        }
+        Err(e) => Incoming::Invalid(Response::new(None, String::new(), Err(e))),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use events::ReallocateMemory;
+    use events::{
+        Authenticate, DeployCode, GetBlock, GetContentionReport, GetDiskUsage, GetLatestBlock,
+        GetTxStatus, GetValueAt, GetValues, ImportSnapshot, Pause, ReallocateMemory, Resume,
+        ScanKeys, Simulate, SubmitBatch, SubmitTx, Subscribe, SubscribeBlocks, SubscribeTxs,
+    };
 
-    use crate::executor::types::TxBody;
+    use crate::block::{
+        builder::{BatchMode, Execute},
+        stream::TxFilter,
+    };
+    use crate::codec;
+    use crate::executor::{
+        engine::EngineConfig,
+        types::{TxBody, TxStatus},
+    };
+    use crate::svm::{object::SVMObject, primitive_types::SVMPrimitives};
+    use crate::test_support::{increment, test_node, tx, ADMIN_TOKEN};
+    use binary::{BinaryResponse, BinaryResult};
+    use events::PROTOCOL_VERSION;
+    use futures::SinkExt;
+    use std::sync::{mpsc, Mutex};
+    use tokio::sync::Notify;
+    use tokio_tungstenite::{connect_async, MaybeTlsStream};
 
     use super::*;
 
@@ -133,8 +412,36 @@ mod tests {
             // events send by frontend
             Message::GetValueAt(GetValueAt {
                 addr: "0x1".to_string(),
+                at: None,
+                prove: false,
+            }),
+            Message::GetValueAt(GetValueAt {
+                addr: "0x1".to_string(),
+                at: Some(42),
+                prove: false,
+            }),
+            Message::GetValueAt(GetValueAt {
+                addr: "0x1".to_string(),
+                at: None,
+                prove: true,
+            }),
+            Message::GetValues(GetValues {
+                addrs: vec!["0x1000001".to_string(), "0x1000002".to_string()],
+                at: None,
+            }),
+            Message::ScanKeys(ScanKeys {
+                prefix: Some("0x100000".to_string()),
+                start: None,
+                end: None,
+                cursor: None,
+                limit: Some(10),
+                at: None,
             }),
             Message::ReallocateMemory(ReallocateMemory {}),
+            Message::GetContentionReport(GetContentionReport {
+                limit: Some(10),
+                reset: false,
+            }),
             Message::SubmitTx(SubmitTx {
                 tx_body: TxBody {
                     tx_hash: "0xtxhash".to_string(),
@@ -143,6 +450,53 @@ mod tests {
                     args: vec![],
                 },
             }),
+            Message::SubmitBatch(SubmitBatch {
+                txs: vec![],
+                mode: BatchMode::Block,
+            }),
+            Message::Simulate(Simulate {
+                tx_body: TxBody {
+                    tx_hash: "0xtxhash".to_string(),
+                    code_hash: "0xcodehash".to_string(),
+                    objs: vec![],
+                    args: vec![],
+                },
+            }),
+            Message::GetBlock(GetBlock { height: 1 }),
+            Message::GetLatestBlock(GetLatestBlock {}),
+            Message::GetTxStatus(GetTxStatus {
+                tx_hash: "0xtxhash".to_string(),
+            }),
+            Message::Subscribe(Subscribe {
+                keys: vec!["0xpot".to_string()],
+                prefixes: vec!["0xduangua/".to_string()],
+            }),
+            Message::SubscribeTxs(SubscribeTxs {
+                filter: TxFilter {
+                    code_hash: Some("0xduangua".to_string()),
+                    obj: None,
+                    status: Some(TxStatus::Committed),
+                },
+                from_height: None,
+                from_tx_seq: Some(1000),
+            }),
+            Message::SubscribeBlocks(SubscribeBlocks {
+                from_height: Some(1),
+            }),
+            Message::Unsubscribe(Unsubscribe { subscription: 1 }),
+            Message::ImportSnapshot(ImportSnapshot {
+                path: "state.snapshot".to_string(),
+            }),
+            Message::GetDiskUsage(GetDiskUsage {}),
+            Message::DeployCode(DeployCode {
+                code_id: "0xecho".to_string(),
+                source: "def main(a):\n  return a\n".to_string(),
+            }),
+            Message::Authenticate(Authenticate {
+                token: ADMIN_TOKEN.to_string(),
+            }),
+            Message::Pause(Pause {}),
+            Message::Resume(Resume {}),
         ];
 
         let events_json = events.iter().map(|e| serde_json::to_string(&e).unwrap());
@@ -150,4 +504,290 @@ mod tests {
             println!("{:?}", ejson)
         }
     }
+
+    #[test]
+    fn envelopes_echo_ids() {
+        let Incoming::Request(id, kind, message) =
+            decode(r#"{"v":1,"id":"7","type":"GetValueAt","body":{"addr":"0x1"}}"#)
+        else {
+            panic!("not an envelope");
+        };
+        assert_eq!((id.as_str(), kind.as_str()), ("7", "GetValueAt"));
+        assert!(matches!(
+            message,
+            Ok(Message::GetValueAt(GetValueAt { addr, at: None, prove: false })) if addr == "0x1"
+        ));
+
+        let Incoming::Request(_, _, message) =
+            decode(r#"{"v":1,"id":"8","type":"GetLatestBlock"}"#)
+        else {
+            panic!("not an envelope");
+        };
+        assert!(matches!(message, Ok(Message::GetLatestBlock(_))));
+
+        for (text, code) in [
+            (
+                r#"{"v":2,"id":"9","type":"GetLatestBlock"}"#,
+                ErrorCode::UnsupportedVersion,
+            ),
+            (
+                r#"{"v":1,"id":"9","type":"DropTables"}"#,
+                ErrorCode::InvalidRequest,
+            ),
+            (
+                r#"{"v":1,"id":"9","type":"GetBlock","body":{}}"#,
+                ErrorCode::InvalidRequest,
+            ),
+        ] {
+            let Incoming::Request(_, _, message) = decode(text) else {
+                panic!("not an envelope {}", text);
+            };
+            assert_eq!(message.unwrap_err().code, code, "{}", text);
+        }
+
+        let Incoming::Invalid(response) = decode(r#"{"v":1,"type":"GetBlock"}"#) else {
+            panic!("envelope without id was accepted");
+        };
+        assert_eq!(response.id, None);
+        assert_eq!(response.kind, "GetBlock");
+        assert_eq!(response.error.unwrap().code, ErrorCode::InvalidRequest);
+
+        assert!(matches!(
+            decode(r#"{"GetLatestBlock":{}}"#),
+            Incoming::Legacy(Message::GetLatestBlock(_))
+        ));
+        assert!(matches!(decode("hello"), Incoming::Unsupported(_)));
+
+        let response = Response::new(Some("7".to_string()), "GetBlock".to_string(), Ok(json!(1)));
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({ "v": 1, "id": "7", "type": "GetBlock", "result": 1 })
+        );
+        let error = ApiError::new(ErrorCode::NotFound, "no block");
+        let response = Response::new(Some("7".to_string()), "GetBlock".to_string(), Err(error));
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({
+                "v": 1,
+                "id": "7",
+                "type": "GetBlock",
+                "error": { "code": "not_found", "message": "no block" }
+            })
+        );
+    }
+
+    #[test]
+    fn binary_envelopes_round_trip() {
+        assert_eq!(Encoding::negotiate(None), Encoding::Json);
+        assert_eq!(
+            Encoding::negotiate(Some("svm.json.v1, svm.bincode.v1")),
+            Encoding::Binary
+        );
+
+        let request = BinaryRequest {
+            v: PROTOCOL_VERSION,
+            id: "7".to_string(),
+            message: Message::GetBlock(GetBlock { height: 3 }),
+        };
+        let Incoming::Request(id, kind, message) = decode_binary(&codec::encode(&request)) else {
+            panic!("not an envelope");
+        };
+        assert_eq!((id.as_str(), kind.as_str()), ("7", "GetBlock"));
+        assert!(matches!(
+            message,
+            Ok(Message::GetBlock(GetBlock { height: 3 }))
+        ));
+        let Incoming::Invalid(response) = decode_binary(b"{}") else {
+            panic!("garbage was accepted");
+        };
+        assert_eq!(response.error.unwrap().code, ErrorCode::InvalidRequest);
+
+        let result = json!({ "height": 3, "block": null, "tags": ["a", -1, 0.5, true] });
+        let response = Response::new(Some(id), kind, Ok(result.clone()));
+        let bytes = codec::encode(&BinaryResponse::from(response));
+        let decoded: BinaryResponse = codec::decode(&bytes).unwrap();
+        assert_eq!(decoded.id.as_deref(), Some("7"));
+        assert!(matches!(decoded.result, Ok(BinaryResult::Other(_))));
+        assert_eq!(Value::from(decoded.result.unwrap()), result);
+
+        // values keep their type
+        let result = json!({ "addr": "0x1", "value": { "Tup": [{ "U24": 5 }, "Era"] } });
+        let response = Response::new(None, "GetValueAt".to_string(), Ok(result.clone()));
+        let bytes = codec::encode(&BinaryResponse::from(response));
+        let decoded = codec::decode::<BinaryResponse>(&bytes)
+            .unwrap()
+            .result
+            .unwrap();
+        let BinaryResult::GetValueAt { value, .. } = &decoded else {
+            panic!("value was not typed: {:?}", decoded);
+        };
+        let expected = SVMPrimitives::Tup(vec![SVMPrimitives::U24(5), SVMPrimitives::Era]);
+        assert_eq!(value.as_ref(), Some(&expected));
+        let mut untyped = Value::from(decoded);
+        untyped.as_object_mut().unwrap().remove("at");
+        assert_eq!(untyped, result);
+    }
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn request(socket: &mut Client, id: &str, kind: &str, body: Value) {
+        let text = json!({ "v": 1, "id": id, "type": kind, "body": body }).to_string();
+        socket.send(WsMessage::Text(text)).await.unwrap();
+    }
+
+    async fn receive(socket: &mut Client) -> Value {
+        let message = socket.next().await.unwrap().unwrap();
+        serde_json::from_str(message.to_text().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn queries_are_answered_while_a_transaction_runs() {
+        let tm = Arc::new(SVMMemory::new());
+        tm.set(
+            b"0x1".to_vec(),
+            SVMObject {
+                value: SVMPrimitives::U24(5),
+                version: 1,
+            },
+        )
+        .unwrap();
+        let started = Arc::new(Notify::new());
+        let (release, released) = mpsc::channel::<()>();
+        let execute: Execute = {
+            let (tm, started, released) = (tm.clone(), started.clone(), Mutex::new(released));
+            Arc::new(move |tx_body: &TxBody| {
+                started.notify_one();
+                released.lock().unwrap().recv().unwrap();
+                increment(&tm, tx_body)
+            })
+        };
+        let engine = EngineConfig {
+            workers: 2,
+            queue: 1,
+        };
+        let node = test_node(tm, engine, Some(execute));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(node)));
+
+        let (mut socket, _) = connect_async(format!("ws://{}", addr)).await.unwrap();
+        request(&mut socket, "1", "SubmitTx", json!({ "tx_body": tx(2) })).await;
+        let accepted = receive(&mut socket).await;
+        assert_eq!(
+            (&accepted["id"], &accepted["type"]),
+            (&json!("1"), &json!("Accepted"))
+        );
+        assert_eq!(accepted["result"]["tx_hashes"], json!(["0xtx2"]));
+
+        started.notified().await;
+        // the queue has room for one transaction only
+        request(&mut socket, "3", "SubmitTx", json!({ "tx_body": tx(4) })).await;
+        let turned_away = receive(&mut socket).await;
+        assert_eq!(
+            (&turned_away["id"], &turned_away["type"]),
+            (&json!("3"), &json!("SubmitTx"))
+        );
+        assert_eq!(turned_away["error"]["code"], "busy");
+        // a batch that could never fit is refused rather than busy
+        let batch = json!({ "txs": [tx(4), tx(6)] });
+        request(&mut socket, "4", "SubmitBatch", batch).await;
+        let refused = receive(&mut socket).await;
+        assert_eq!(refused["id"], "4");
+        assert_eq!(refused["error"]["code"], "invalid_argument");
+
+        request(&mut socket, "2", "GetValueAt", json!({ "addr": "0x1" })).await;
+        let answered = receive(&mut socket).await;
+        assert_eq!(answered["id"], "2");
+        assert_eq!(answered["result"]["value"], json!({ "U24": 5 }));
+
+        release.send(()).unwrap();
+        let submitted = receive(&mut socket).await;
+        assert_eq!(
+            (&submitted["id"], &submitted["type"]),
+            (&json!("1"), &json!("SubmitTx"))
+        );
+        assert_eq!(submitted["result"]["status"], true);
+        assert_eq!(submitted["result"]["ret_value"], json!({ "U24": 2 }));
+    }
+
+    #[tokio::test]
+    async fn subscriptions_push_after_their_reply() {
+        let tm = Arc::new(SVMMemory::new());
+        let execute: Execute = {
+            let tm = tm.clone();
+            Arc::new(move |tx_body: &TxBody| increment(&tm, tx_body))
+        };
+        let node = test_node(tm, EngineConfig::default(), Some(execute));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(node)));
+        let (mut socket, _) = connect_async(format!("ws://{}", addr)).await.unwrap();
+        request(&mut socket, "1", "SubmitTx", json!({ "tx_body": tx(2) })).await;
+        receive(&mut socket).await;
+        receive(&mut socket).await;
+
+        // block 1 can be pushed at once, yet the reply comes first
+        let from = json!({ "from_height": 1 });
+        request(&mut socket, "2", "SubscribeBlocks", from).await;
+        let reply = receive(&mut socket).await;
+        assert_eq!(reply["id"], "2");
+        let subscription = &reply["result"]["subscription"];
+        let pushed = receive(&mut socket).await;
+        assert_eq!(
+            (&pushed["type"], &pushed["result"]["subscription"]),
+            (&json!("Block"), subscription)
+        );
+        assert_eq!(pushed["result"]["height"], 1);
+    }
+
+    #[tokio::test]
+    async fn admin_messages_need_the_token() {
+        let node = test_node(Arc::new(SVMMemory::new()), EngineConfig::default(), None);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(node)));
+        let (mut socket, _) = connect_async(format!("ws://{}", addr)).await.unwrap();
+
+        request(&mut socket, "1", "Pause", json!({})).await;
+        assert_eq!(receive(&mut socket).await["error"]["code"], "unauthorized");
+        let legacy = json!({ "ReallocateMemory": {} }).to_string();
+        socket.send(WsMessage::Text(legacy)).await.unwrap();
+        assert_eq!(receive(&mut socket).await["error"]["code"], "unauthorized");
+        request(
+            &mut socket,
+            "2",
+            "Authenticate",
+            json!({ "token": "guess" }),
+        )
+        .await;
+        assert_eq!(receive(&mut socket).await["error"]["code"], "unauthorized");
+
+        request(
+            &mut socket,
+            "3",
+            "Authenticate",
+            json!({ "token": ADMIN_TOKEN }),
+        )
+        .await;
+        assert_eq!(
+            receive(&mut socket).await["result"],
+            json!({ "admin": true })
+        );
+        request(&mut socket, "4", "Pause", json!({})).await;
+        assert_eq!(
+            receive(&mut socket).await["result"],
+            json!({ "paused": true })
+        );
+        request(&mut socket, "5", "SubmitTx", json!({ "tx_body": tx(2) })).await;
+        let refused = receive(&mut socket).await;
+        assert_eq!(
+            (&refused["id"], &refused["error"]["code"]),
+            (&json!("5"), &json!("busy"))
+        );
+        request(&mut socket, "6", "Resume", json!({})).await;
+        assert_eq!(
+            receive(&mut socket).await["result"],
+            json!({ "paused": false })
+        );
+    }
 }
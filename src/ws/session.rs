@@ -0,0 +1,325 @@
+use super::binary::{BinaryResponse, Encoding};
+use super::events::{ApiError, ErrorCode, Response, Subscribe, SubscribeBlocks, SubscribeTxs};
+use super::limits::ConnectionLimits;
+use crate::block::{store::BlockStore, stream::follow};
+use crate::block_stm::{
+    subscriptions::{Filter, Subscription},
+    svm_memory::SVMMemory,
+};
+use crate::codec;
+use futures::{stream::SplitSink, SinkExt};
+use log::debug;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{
+    net::TcpStream,
+    sync::{mpsc, watch, OwnedSemaphorePermit},
+    task::AbortHandle,
+};
+use tokio_tungstenite::{tungstenite::Message as WsMessage, WebSocketStream};
+
+type WsSink = SplitSink<WebSocketStream<TcpStream>, WsMessage>;
+
+/// Messages queued for a connection before senders wait for it to drain.
+pub const OUTBOUND_QUEUE: usize = 1024;
+
+/// Changes once the reply to a request is queued, or is dropped with it;
+/// the messages of a subscription it starts wait for it, so they never come
+/// before the subscription id.
+pub type Replied = watch::Receiver<()>;
+
+/// What a subscription of a connection follows.
+enum Feed {
+    /// id of the subscription to object changes
+    Keys(u64),
+    /// task streaming transactions or blocks
+    Chain(AbortHandle),
+}
+
+/// One WebSocket connection: the queue of replies and pushed messages to
+/// write, how they are encoded, whether it authenticated as admin, its
+/// limits and the subscriptions it holds, numbered per connection.
+pub struct Session {
+    outbound: mpsc::Sender<WsMessage>,
+    encoding: Encoding,
+    peer: SocketAddr,
+    admin: AtomicBool,
+    limits: ConnectionLimits,
+    next_id: AtomicU64,
+    /// id -> feed, with its room in the subscription limits
+    subscriptions: Mutex<HashMap<u64, (Feed, OwnedSemaphorePermit)>>,
+}
+
+impl Session {
+    /// Starts a task writing the queued messages to `sink`, in order, until
+    /// the session is dropped or the connection fails.
+    pub fn new(
+        sink: WsSink,
+        encoding: Encoding,
+        peer: SocketAddr,
+        limits: ConnectionLimits,
+    ) -> Self {
+        let (outbound, queued) = mpsc::channel(OUTBOUND_QUEUE);
+        tokio::spawn(write_outbound(sink, queued));
+        Self {
+            outbound,
+            encoding,
+            peer,
+            admin: AtomicBool::new(false),
+            limits,
+            next_id: AtomicU64::new(1),
+            subscriptions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    pub fn limits(&self) -> &ConnectionLimits {
+        &self.limits
+    }
+
+    /// Whether the connection sent the admin token.
+    pub fn is_admin(&self) -> bool {
+        self.admin.load(Ordering::Relaxed)
+    }
+
+    pub fn grant_admin(&self) {
+        self.admin.store(true, Ordering::Relaxed);
+    }
+
+    fn new_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Queues `message`, returning false once the connection is gone.
+    pub async fn send(&self, message: WsMessage) -> bool {
+        self.outbound.send(message).await.is_ok()
+    }
+
+    /// `response` in a frame of the connection's encoding.
+    pub fn encode(&self, response: Response) -> WsMessage {
+        match self.encoding {
+            Encoding::Json => WsMessage::Text(serde_json::to_string(&response).unwrap()),
+            Encoding::Binary => WsMessage::Binary(codec::encode(&BinaryResponse::from(response))),
+        }
+    }
+
+    pub fn subscribe(
+        self: &Arc<Self>,
+        tm: &SVMMemory,
+        Subscribe { keys, prefixes }: Subscribe,
+        replied: &Replied,
+    ) -> Result<Value, ApiError> {
+        if keys.is_empty() && prefixes.is_empty() {
+            return Err(ApiError::new(
+                ErrorCode::InvalidArgument,
+                "subscribe to at least one key or prefix",
+            ));
+        }
+        let filter = Filter {
+            keys: keys.into_iter().map(String::into_bytes).collect(),
+            prefixes: prefixes.into_iter().map(String::into_bytes).collect(),
+        };
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let slot = self.limits.subscribe(subscriptions.len())?;
+        let subscription = tm.subscriptions().subscribe(filter);
+        let id = self.new_id();
+        subscriptions.insert(id, (Feed::Keys(subscription.id), slot));
+        tokio::spawn(forward_changes(
+            self.clone(),
+            id,
+            subscription,
+            replied.clone(),
+        ));
+        Ok(json!({ "subscription": id }))
+    }
+
+    /// Streams every transaction matching the filter, from transaction
+    /// number `from_tx_seq` or the first one of block `from_height` on, or
+    /// from the next block if neither is given.
+    pub fn subscribe_txs(
+        self: &Arc<Self>,
+        store: &Arc<BlockStore>,
+        SubscribeTxs {
+            filter,
+            from_height,
+            from_tx_seq,
+        }: SubscribeTxs,
+        replied: &Replied,
+    ) -> Result<Value, ApiError> {
+        filter
+            .validate()
+            .map_err(|e| ApiError::new(ErrorCode::InvalidArgument, e))?;
+        let from_tx_seq = from_tx_seq.unwrap_or(0);
+        let from_height = match from_height {
+            Some(height) => height,
+            None if from_tx_seq > 0 => store.height_of_tx(from_tx_seq),
+            None => store.height() + 1,
+        };
+
+        // locked until the task is registered, so it cannot end before that
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let slot = self.limits.subscribe(subscriptions.len())?;
+        let (session, store, id) = (self.clone(), store.clone(), self.new_id());
+        let mut replied = replied.clone();
+        let task = tokio::spawn(async move {
+            _ = replied.changed().await;
+            follow(&store, from_height, |block, first_tx_seq| {
+                let mut messages = vec![];
+                for (index, (tx_body, receipt)) in block.txs.iter().zip(&block.receipts).enumerate()
+                {
+                    let tx_seq = first_tx_seq + index as u64;
+                    if tx_seq < from_tx_seq || !filter.matches(tx_body, receipt) {
+                        continue;
+                    }
+                    let result = json!({
+                        "subscription": id,
+                        "height": block.height,
+                        "tx_seq": tx_seq,
+                        "tx": tx_body,
+                        "receipt": receipt
+                    });
+                    messages.push(Response::new(None, "Tx".to_string(), Ok(result)));
+                }
+                let session = session.clone();
+                async move { session.push_all(messages).await }
+            })
+            .await;
+            session.subscriptions.lock().unwrap().remove(&id);
+        });
+        subscriptions.insert(id, (Feed::Chain(task.abort_handle()), slot));
+        Ok(json!({ "subscription": id, "from_height": from_height }))
+    }
+
+    /// Streams the header of every block from `from_height` on, or from the
+    /// next block.
+    pub fn subscribe_blocks(
+        self: &Arc<Self>,
+        store: &Arc<BlockStore>,
+        SubscribeBlocks { from_height }: SubscribeBlocks,
+        replied: &Replied,
+    ) -> Result<Value, ApiError> {
+        let from_height = from_height.unwrap_or(store.height() + 1);
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let slot = self.limits.subscribe(subscriptions.len())?;
+        let (session, store, id) = (self.clone(), store.clone(), self.new_id());
+        let mut replied = replied.clone();
+        let task = tokio::spawn(async move {
+            _ = replied.changed().await;
+            follow(&store, from_height, |block, first_tx_seq| {
+                let result = json!({
+                    "subscription": id,
+                    "height": block.height,
+                    "parent_hash": block.parent_hash,
+                    "timestamp": block.timestamp,
+                    "state_root": block.state_root,
+                    "hash": block.hash,
+                    "txs": block.txs.len(),
+                    "first_tx_seq": first_tx_seq
+                });
+                let message = Response::new(None, "Block".to_string(), Ok(result));
+                let session = session.clone();
+                async move { session.push_all(vec![message]).await }
+            })
+            .await;
+            session.subscriptions.lock().unwrap().remove(&id);
+        });
+        subscriptions.insert(id, (Feed::Chain(task.abort_handle()), slot));
+        Ok(json!({ "subscription": id, "from_height": from_height }))
+    }
+
+    pub fn unsubscribe(&self, tm: &SVMMemory, id: u64) -> Result<Value, ApiError> {
+        let Some((feed, _slot)) = self.subscriptions.lock().unwrap().remove(&id) else {
+            return Err(ApiError::new(
+                ErrorCode::NotFound,
+                format!("no subscription={} on this connection", id),
+            ));
+        };
+        stop(tm, feed);
+        Ok(json!({ "subscription": id }))
+    }
+
+    /// Drops every subscription, once the connection is gone.
+    pub fn close(&self, tm: &SVMMemory) {
+        for (_, (feed, _slot)) in self.subscriptions.lock().unwrap().drain() {
+            stop(tm, feed);
+        }
+    }
+
+    /// Sends pushed messages in order, returning false once the connection is gone.
+    async fn push_all(&self, messages: Vec<Response>) -> bool {
+        for message in messages {
+            if !self.send(self.encode(message)).await {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn stop(tm: &SVMMemory, feed: Feed) {
+    match feed {
+        Feed::Keys(id) => {
+            tm.subscriptions().unsubscribe(id);
+        }
+        Feed::Chain(task) => task.abort(),
+    }
+}
+
+async fn write_outbound(mut sink: WsSink, mut queued: mpsc::Receiver<WsMessage>) {
+    while let Some(message) = queued.recv().await {
+        if let Err(e) = sink.send(message).await {
+            debug!("ws write failed: {}", e);
+            return;
+        }
+    }
+    _ = sink.close().await;
+}
+
+/// Pushes the changes of `subscription` to the connection until it is
+/// dropped, ending with a `Lagged` message if it fell behind.
+async fn forward_changes(
+    session: Arc<Session>,
+    id: u64,
+    mut subscription: Subscription,
+    mut replied: Replied,
+) {
+    _ = replied.changed().await;
+    let mut last_seq = None;
+    while let Some(changes) = subscription.changes.recv().await {
+        last_seq = Some(changes.seq);
+        let result = json!({
+            "subscription": id,
+            "seq": changes.seq,
+            "changes": changes
+                .objects
+                .iter()
+                .map(|(key, object)| json!({
+                    "addr": String::from_utf8_lossy(key),
+                    "value": object.value,
+                    "version": object.version
+                }))
+                .collect::<Vec<_>>()
+        });
+        let message = Response::new(None, "Changes".to_string(), Ok(result));
+        if !session.push_all(vec![message]).await {
+            return;
+        }
+    }
+
+    if subscription.lagged() {
+        session.subscriptions.lock().unwrap().remove(&id);
+        let result = json!({ "subscription": id, "last_seq": last_seq });
+        let message = Response::new(None, "Lagged".to_string(), Ok(result));
+        session.push_all(vec![message]).await;
+    }
+}